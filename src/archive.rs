@@ -0,0 +1,102 @@
+//! Archiving finished projects.
+//!
+//! A project is finished once all its tasks are checked and all its
+//! sub-projects are archived. Todoist doesn't say when that happened, so the
+//! clock starts in the round that first sees it, and only for projects that
+//! were seen with open tasks before. A project that has just been created, or
+//! was already finished when we first looked, is never touched.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use {TaskTree, Node, NodeType, Project};
+
+/// The settings of auto-archiving.
+#[derive(Debug, Clone)]
+pub struct ArchiveRules {
+    /// How long a project stays after its last task is done.
+    pub grace: Duration,
+    /// Ids or names of projects never to archive, names regardless of case.
+    /// The inbox is left alone anyway.
+    pub exclude: Vec<String>,
+}
+
+impl Default for ArchiveRules {
+    fn default() -> ArchiveRules {
+        ArchiveRules {
+            grace: Duration::new(24 * 60 * 60, 0),
+            exclude: vec!["Inbox".into()],
+        }
+    }
+}
+
+impl ArchiveRules {
+    fn excludes(&self, project: &Project) -> bool {
+        let (id, name) = (project.id.to_string(), project.name.to_lowercase());
+        project.extra.get("inbox_project").and_then(|inbox| inbox.as_bool()).unwrap_or(false) ||
+        self.exclude.iter().any(|key| *key == id || key.to_lowercase() == name)
+    }
+}
+
+/// What we know about the projects of the tree between rounds.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ArchiveWatch {
+    /// Projects seen with open tasks, and since when they are finished, in
+    /// seconds since the epoch.
+    finished: BTreeMap<usize, Option<u64>>,
+}
+
+impl ArchiveWatch {
+    /// Look at the projects of `tree` at `now`, in seconds since the epoch,
+    /// and return the ones to archive, sub-projects first.
+    pub fn update(&mut self, tree: &TaskTree, rules: &ArchiveRules, now: u64) -> Vec<usize> {
+        let mut seen = BTreeMap::new();
+        let mut archive = vec![];
+        for node in &tree.nodes {
+            self.visit(node, rules, now, &mut seen, &mut archive);
+        }
+        // archived or deleted projects are out of the tree
+        self.finished = seen;
+        archive
+    }
+
+    fn visit(&self,
+             node: &Node,
+             rules: &ArchiveRules,
+             now: u64,
+             seen: &mut BTreeMap<usize, Option<u64>>,
+             archive: &mut Vec<usize>) {
+        for child in &node.nodes {
+            self.visit(child, rules, now, seen, archive);
+        }
+        let project = match node.ntype {
+            NodeType::ProjectNodeType(ref project) => project,
+            NodeType::ItemNodeType(_) | NodeType::SectionNodeType(_) => return,
+        };
+        if !is_finished(node) {
+            seen.insert(project.id, None);
+            return;
+        }
+        let since = match self.finished.get(&project.id) {
+            // never seen open
+            None => return,
+            Some(&None) => now,
+            Some(&Some(since)) => since,
+        };
+        seen.insert(project.id, Some(since));
+        if now >= since + rules.grace.as_secs() && !rules.excludes(project) {
+            archive.push(project.id);
+        }
+    }
+}
+
+/// No open task in the project and no sub-project left.
+fn is_finished(node: &Node) -> bool {
+    node.nodes.iter().all(|child| {
+        match child.ntype {
+            NodeType::ItemNodeType(_) => child.checked() && is_finished(child),
+            NodeType::SectionNodeType(_) => is_finished(child),
+            NodeType::ProjectNodeType(_) => false,
+        }
+    })
+}
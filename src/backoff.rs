@@ -0,0 +1,50 @@
+//! Exponential backoff with jitter, for requests that Todoist throttles or
+//! fails to serve.
+
+use std::cmp::{max, min};
+use std::time::Duration;
+
+use rand;
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    cap: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    /// Delays start at `base` and double with each failure, up to `cap`.
+    pub fn new(base: Duration, cap: Duration) -> Backoff {
+        Backoff {
+            base: base,
+            cap: cap,
+            failures: 0,
+        }
+    }
+
+    /// Failures since the last success.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Count a failure and return how long to wait before trying again.
+    /// The delay is picked at random from the upper half of the current
+    /// step, so clients failing together don't come back together, and is
+    /// never shorter than `retry_after`.
+    pub fn fail(&mut self, retry_after: Option<Duration>) -> Duration {
+        self.failures += 1;
+        let step = min(millis(self.base).saturating_mul(1 << min(self.failures, 20)),
+                       millis(self.cap));
+        let delay = step / 2 + (rand::random::<f64>() * (step / 2) as f64) as u64;
+        max(Duration::from_millis(delay), retry_after.unwrap_or_default())
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64
+}
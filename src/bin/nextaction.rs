@@ -1,16 +1,22 @@
 extern crate nextaction;
-#[macro_use]
 extern crate log;
 extern crate env_logger;
 extern crate config;
+extern crate serde_json;
 #[macro_use]
 extern crate error_chain;
 
-use nextaction::{NextAction, ErrorKind, Result, Error};
+use nextaction::{Account, WebhookSettings, ApiVersion, Marker, Grouping, Result};
+use nextaction::daemon;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
-use config::{Config, File, FileFormat, Environment};
+use config::{Config, File, FileFormat, Environment, Value};
+use env_logger::LogBuilder;
+use log::LogRecord;
 
 quick_main!(run);
 
@@ -21,29 +27,226 @@ fn run() -> Result<()> {
 
     c.merge(Environment::new("NXTT")).unwrap();
 
-    ::env_logger::init().unwrap();
+    // every account logs from a thread named after it
+    let mut logger = LogBuilder::new();
+    logger.format(|record: &LogRecord| {
+        format!("{}:{}:{}: {}",
+                record.level(),
+                thread::current().name().unwrap_or("-"),
+                record.location().module_path(),
+                record.args())
+    });
+    if let Ok(spec) = env::var("RUST_LOG") {
+        logger.parse(&spec);
+    }
+    logger.init().unwrap();
+
+    let accounts = accounts(&c)?;
+    if accounts.is_empty() {
+        panic!("You need to set the NXTT_TOKEN or list [[accounts]] in Config.toml");
+    }
+
+    // `--dry-run [--json]` prints the plan of a single round without sending it
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--dry-run") {
+        for account in &accounts {
+            let mut na = account.next_action();
+            na.dry_run = true;
+            let plan = na.step()?;
+            if accounts.len() > 1 {
+                println!("== {}", account.name);
+            }
+            if args.iter().any(|a| a == "--json") {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            } else {
+                print!("{}", plan);
+            }
+        }
+        return Ok(());
+    }
+
+    // each account restarts on its own after an error, the others carry on
+    daemon::run(accounts)
+}
 
-    let token = c.get_str("TOKEN").expect("You need to set the NXTT_TOKEN");
+fn api_version(version: i64) -> Result<ApiVersion> {
+    match version {
+        7 => Ok(ApiVersion::V7),
+        9 => Ok(ApiVersion::V9),
+        other => bail!("Unsupported API version {}, use 7 or 9", other),
+    }
+}
 
-    let interval = c.get_int("INTERVAL").unwrap_or(10) as u64;
+/// Markers like `suffix:-`, `prefix:>>` or `tag:[seq]`.
+fn markers(specs: Vec<String>) -> Result<Vec<Marker>> {
+    specs.iter().map(|spec| spec.parse()).collect()
+}
 
-    let mut na = NextAction::new(&token);
+/// Project ids or names mapped to `parallel` or `sequential`.
+fn project_modes(table: HashMap<String, Value>) -> Result<BTreeMap<String, Grouping>> {
+    table.into_iter()
+        .map(|(project, mode)| {
+            let mode = match mode.into_str() {
+                Some(mode) => mode,
+                None => bail!("The mode of project '{}' needs to be a string", project),
+            };
+            Ok((project, mode.parse()?))
+        })
+        .collect()
+}
 
-    c.get("NEXTACTION_NAME").map(|n| na.nextaction_name = n.into_str().unwrap());
-    c.get("SOMEDAY_NAME").map(|n| na.someday_name = n.into_str().unwrap());
+// review intervals are given in days
+fn days(n: i64) -> Duration {
+    Duration::new(n as u64 * 24 * 60 * 60, 0)
+}
 
-    let mut result = na.loopit(interval);
-    loop {
-        match result {
-            Err(Error(ErrorKind::HyperError(err), _)) => {
-                warn!("Network issue '{:?}', continuing the loop", err)
-            }
-            Err(err) => {
-                error!("Unexpected error: '{:?}', exiting...", err);
-                thread::sleep(Duration::new(1, 0));
-            }
-            Ok(_) => {}
+fn review_intervals(table: HashMap<String, Value>) -> BTreeMap<String, Duration> {
+    table.into_iter()
+        .map(|(project, n)| (project, days(n.into_int().expect("Review intervals need to be numbers of days"))))
+        .collect()
+}
+
+fn strings(values: Vec<Value>) -> Vec<String> {
+    values.into_iter().map(|value| value.into_str().expect("Markers need to be strings")).collect()
+}
+
+// with a client secret, rounds follow webhook deliveries and polling slows down to a fallback
+fn webhook(c: &Config, secret: Option<String>, addr: Option<String>) -> Option<WebhookSettings> {
+    secret.map(|secret| {
+        WebhookSettings {
+            addr: addr.unwrap_or("0.0.0.0:8000".into()),
+            secret: secret,
+            debounce: Duration::new(c.get_int("WEBHOOK_DEBOUNCE").unwrap_or(2) as u64, 0),
+            fallback: Duration::new(c.get_int("FALLBACK_INTERVAL").unwrap_or(10 * 60) as u64, 0),
         }
-        result = na.loopit(interval);
+    })
+}
+
+/// The settings at the top level of the config, which every account starts with.
+fn defaults(c: &Config) -> Result<Account> {
+    let mut account = Account::new("default", "");
+    c.get_int("INTERVAL").map(|n| account.interval = n as u64);
+    c.get_str("NEXTACTION_NAME").map(|n| account.nextaction_name = n);
+    c.get_str("SOMEDAY_NAME").map(|n| account.someday_name = n);
+    c.get_bool("INCREMENTAL").map(|b| account.incremental = b);
+    c.get_str("STATE_DIR").map(|dir| account.state_dir = Some(dir.into()));
+    c.get_int("BATCH_SIZE").map(|n| account.batch_size = Some(n as usize));
+    if let Some(v) = c.get_int("API_VERSION") {
+        account.api_version = api_version(v)?;
+    }
+    // a list in Config.toml, separated by spaces in the environment
+    let specs = |key: &str| {
+        c.get_array(key)
+            .map(strings)
+            .or_else(|| c.get_str(key).map(|s| s.split_whitespace().map(|s| s.to_string()).collect()))
+    };
+    if let Some(specs) = specs("PARALLEL_MARKERS") {
+        account.markers.parallel = markers(specs)?;
+    }
+    if let Some(specs) = specs("SEQUENTIAL_MARKERS") {
+        account.markers.sequential = markers(specs)?;
+    }
+    c.get_str("PARALLEL_LABEL").map(|n| account.parallel_label = Some(n));
+    c.get_str("SEQUENTIAL_LABEL").map(|n| account.sequential_label = Some(n));
+    if let Some(modes) = c.get_table("PROJECT_MODES") {
+        account.project_modes = project_modes(modes)?;
     }
+    if let Some(specs) = specs("ON_HOLD_MARKERS") {
+        account.on_hold_markers = markers(specs)?;
+    }
+    specs("ON_HOLD_PROJECTS").map(|names| account.on_hold_projects = names);
+    c.get_bool("COMPLETE_PARALLEL").map(|b| account.complete_parallel = b);
+    c.get_bool("COMPLETE_SEQUENTIAL").map(|b| account.complete_sequential = b);
+    c.get_bool("ARCHIVE_PROJECTS").map(|b| account.archive_projects = b);
+    c.get_int("ARCHIVE_GRACE").map(|n| account.archive.grace = Duration::new(n as u64, 0));
+    specs("ARCHIVE_EXCLUDE").map(|names| account.archive.exclude = names);
+    c.get_bool("REVIEW_PROJECTS").map(|b| account.review_projects = b);
+    c.get_int("REVIEW_INTERVAL").map(|n| account.review.interval = days(n));
+    c.get_table("REVIEW_INTERVALS").map(|table| account.review.intervals = review_intervals(table));
+    c.get_str("REVIEW_TASK").map(|task| account.review.task = task);
+    Ok(account)
+}
+
+/// A single account from `NXTT_TOKEN`, or every entry of `[[accounts]]`.
+fn accounts(c: &Config) -> Result<Vec<Account>> {
+    let defaults = defaults(c)?;
+    let list = match c.get_array("ACCOUNTS") {
+        Some(list) => list,
+        None => {
+            return Ok(c.get_str("TOKEN")
+                .map(|token| {
+                    vec![Account {
+                             token: token,
+                             webhook: webhook(c, c.get_str("WEBHOOK_SECRET"), c.get_str("WEBHOOK_ADDR")),
+                             ..defaults
+                         }]
+                })
+                .unwrap_or(vec![]));
+        }
+    };
+    list.into_iter()
+        .map(|entry| -> Result<Account> {
+            let mut table: HashMap<String, Value> = entry.into_table().expect("Every account needs to be a table");
+            let mut take = |key: &str| table.remove(key);
+            let name = take("name").and_then(Value::into_str).expect("Every account needs a name");
+            let token = take("token")
+                .and_then(Value::into_str)
+                .expect(&format!("Account '{}' needs a token", name));
+            // the accounts share the state dir, each in a directory of its own
+            let state_dir = take("state_dir")
+                .and_then(Value::into_str)
+                .map(PathBuf::from)
+                .or(defaults.state_dir.as_ref().map(|dir| dir.join(&name)));
+            let mut account = Account {
+                name: name,
+                token: token,
+                state_dir: state_dir,
+                webhook: webhook(c,
+                                 take("webhook_secret").and_then(Value::into_str),
+                                 take("webhook_addr").and_then(Value::into_str)),
+                ..defaults.clone()
+            };
+            take("interval").and_then(Value::into_int).map(|n| account.interval = n as u64);
+            take("nextaction_name").and_then(Value::into_str).map(|n| account.nextaction_name = n);
+            take("someday_name").and_then(Value::into_str).map(|n| account.someday_name = n);
+            take("incremental").and_then(Value::into_bool).map(|b| account.incremental = b);
+            take("batch_size").and_then(Value::into_int).map(|n| account.batch_size = Some(n as usize));
+            if let Some(v) = take("api_version").and_then(Value::into_int) {
+                account.api_version = api_version(v)?;
+            }
+            if let Some(specs) = take("parallel_markers").and_then(Value::into_array) {
+                account.markers.parallel = markers(strings(specs))?;
+            }
+            if let Some(specs) = take("sequential_markers").and_then(Value::into_array) {
+                account.markers.sequential = markers(strings(specs))?;
+            }
+            take("parallel_label").and_then(Value::into_str).map(|n| account.parallel_label = Some(n));
+            take("sequential_label").and_then(Value::into_str).map(|n| account.sequential_label = Some(n));
+            if let Some(modes) = take("project_modes").and_then(Value::into_table) {
+                account.project_modes = project_modes(modes)?;
+            }
+            if let Some(specs) = take("on_hold_markers").and_then(Value::into_array) {
+                account.on_hold_markers = markers(strings(specs))?;
+            }
+            take("on_hold_projects")
+                .and_then(Value::into_array)
+                .map(|names| account.on_hold_projects = strings(names));
+            take("complete_parallel").and_then(Value::into_bool).map(|b| account.complete_parallel = b);
+            take("complete_sequential").and_then(Value::into_bool).map(|b| account.complete_sequential = b);
+            take("archive_projects").and_then(Value::into_bool).map(|b| account.archive_projects = b);
+            take("archive_grace")
+                .and_then(Value::into_int)
+                .map(|n| account.archive.grace = Duration::new(n as u64, 0));
+            take("archive_exclude")
+                .and_then(Value::into_array)
+                .map(|names| account.archive.exclude = strings(names));
+            take("review_projects").and_then(Value::into_bool).map(|b| account.review_projects = b);
+            take("review_interval").and_then(Value::into_int).map(|n| account.review.interval = days(n));
+            take("review_intervals")
+                .and_then(Value::into_table)
+                .map(|table| account.review.intervals = review_intervals(table));
+            take("review_task").and_then(Value::into_str).map(|task| account.review.task = task);
+            Ok(account)
+        })
+        .collect()
 }
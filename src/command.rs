@@ -0,0 +1,369 @@
+//! Typed sync commands, serialized the way the sync endpoint expects them:
+//! `{"type": ..., "args": {...}, "uuid": ..., "temp_id": ...}`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use uuid::Uuid;
+
+/// Names an object created by a command until Todoist hands out the real id.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TempId(pub Uuid);
+
+impl TempId {
+    pub fn new() -> TempId {
+        TempId(Uuid::new_v4())
+    }
+}
+
+impl fmt::Display for TempId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Identifies a command, Todoist reports its outcome under it. Sending a command
+/// twice with the same id has no further effect.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommandId(pub Uuid);
+
+impl CommandId {
+    pub fn new() -> CommandId {
+        CommandId(Uuid::new_v4())
+    }
+}
+
+impl fmt::Display for CommandId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Either a real id or the temp id of an object created earlier in the same queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObjectId {
+    Id(usize),
+    Temp(TempId),
+}
+
+impl ObjectId {
+    /// Swap a temp id for the real id once Todoist has handed it out.
+    pub fn resolve(&mut self, mapping: &BTreeMap<TempId, usize>) {
+        if let ObjectId::Temp(temp_id) = *self {
+            if let Some(&id) = mapping.get(&temp_id) {
+                *self = ObjectId::Id(id);
+            }
+        }
+    }
+}
+
+impl From<usize> for ObjectId {
+    fn from(id: usize) -> ObjectId {
+        ObjectId::Id(id)
+    }
+}
+
+impl From<TempId> for ObjectId {
+    fn from(id: TempId) -> ObjectId {
+        ObjectId::Temp(id)
+    }
+}
+
+impl Serialize for ObjectId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        match *self {
+            ObjectId::Id(id) => serializer.serialize_u64(id as u64),
+            ObjectId::Temp(ref temp_id) => temp_id.serialize(serializer),
+        }
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjectId::Id(id) => id.fmt(f),
+            ObjectId::Temp(ref temp_id) => temp_id.fmt(f),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "args", rename_all = "snake_case")]
+pub enum CommandKind {
+    LabelAdd { name: String },
+    ItemAdd {
+        content: String,
+        project_id: ObjectId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parent_id: Option<ObjectId>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        indent: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        item_order: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        labels: Option<Vec<ObjectId>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        priority: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        date_string: Option<String>,
+    },
+    ItemUpdate {
+        id: ObjectId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        labels: Option<Vec<ObjectId>>,
+    },
+    /// Moves items, keyed by the project they are in now, to `to_project`.
+    ItemMove {
+        project_items: BTreeMap<ObjectId, Vec<ObjectId>>,
+        to_project: ObjectId,
+    },
+    ItemDelete { ids: Vec<ObjectId> },
+    CloseItem { id: ObjectId },
+    ItemUncomplete { ids: Vec<ObjectId> },
+    /// Sets `[item_order, indent]` of each item.
+    ItemUpdateOrdersIndents { ids_to_orders_indents: BTreeMap<ObjectId, (usize, usize)> },
+    ProjectAdd {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parent_id: Option<ObjectId>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        indent: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        item_order: Option<usize>,
+    },
+    ProjectUpdate {
+        id: ObjectId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        collapsed: Option<usize>,
+    },
+    ProjectArchive { ids: Vec<ObjectId> },
+    /// Sets `[item_order, indent]` of each project.
+    ProjectUpdateOrdersIndents { ids_to_orders_indents: BTreeMap<ObjectId, (usize, usize)> },
+    NoteAdd { item_id: ObjectId, content: String },
+}
+
+fn resolve_all(ids: &mut Vec<ObjectId>, mapping: &BTreeMap<TempId, usize>) {
+    for id in ids {
+        id.resolve(mapping);
+    }
+}
+
+fn resolve_keys<V>(map: &mut BTreeMap<ObjectId, V>, mapping: &BTreeMap<TempId, usize>) {
+    let entries = ::std::mem::replace(map, BTreeMap::new());
+    for (mut id, value) in entries {
+        id.resolve(mapping);
+        map.insert(id, value);
+    }
+}
+
+impl CommandKind {
+    /// Whether `later` can be folded into this command, which holds for two
+    /// `item_update`s of the same item.
+    pub fn merges_with(&self, later: &CommandKind) -> bool {
+        match (self, later) {
+            (&CommandKind::ItemUpdate { ref id, .. }, &CommandKind::ItemUpdate { id: ref later_id, .. }) => {
+                id == later_id
+            }
+            _ => false,
+        }
+    }
+
+    /// Swap the temp ids in the arguments for real ids found in `mapping`.
+    pub fn resolve(&mut self, mapping: &BTreeMap<TempId, usize>) {
+        match *self {
+            CommandKind::LabelAdd { .. } => {}
+            CommandKind::ItemAdd { ref mut project_id, ref mut parent_id, ref mut labels, .. } => {
+                project_id.resolve(mapping);
+                for id in parent_id.iter_mut() {
+                    id.resolve(mapping);
+                }
+                for labels in labels.iter_mut() {
+                    resolve_all(labels, mapping);
+                }
+            }
+            CommandKind::ItemUpdate { ref mut id, ref mut labels } => {
+                id.resolve(mapping);
+                for labels in labels.iter_mut() {
+                    resolve_all(labels, mapping);
+                }
+            }
+            CommandKind::ItemMove { ref mut project_items, ref mut to_project } => {
+                resolve_keys(project_items, mapping);
+                for ids in project_items.values_mut() {
+                    resolve_all(ids, mapping);
+                }
+                to_project.resolve(mapping);
+            }
+            CommandKind::ItemDelete { ref mut ids } |
+            CommandKind::ItemUncomplete { ref mut ids } |
+            CommandKind::ProjectArchive { ref mut ids } => resolve_all(ids, mapping),
+            CommandKind::CloseItem { ref mut id } |
+            CommandKind::ProjectUpdate { ref mut id, .. } => id.resolve(mapping),
+            CommandKind::ItemUpdateOrdersIndents { ref mut ids_to_orders_indents } |
+            CommandKind::ProjectUpdateOrdersIndents { ref mut ids_to_orders_indents } => {
+                resolve_keys(ids_to_orders_indents, mapping)
+            }
+            CommandKind::ProjectAdd { ref mut parent_id, .. } => {
+                for id in parent_id.iter_mut() {
+                    id.resolve(mapping);
+                }
+            }
+            CommandKind::NoteAdd { ref mut item_id, .. } => item_id.resolve(mapping),
+        }
+    }
+
+    /// Fold `later` into this command, the later values win.
+    pub fn merge(&mut self, later: &CommandKind) {
+        if !self.merges_with(later) {
+            return;
+        }
+        if let (&mut CommandKind::ItemUpdate { ref mut labels, .. },
+                &CommandKind::ItemUpdate { labels: ref later_labels, .. }) = (self, later) {
+            if later_labels.is_some() {
+                *labels = later_labels.clone();
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Command {
+    #[serde(flatten)]
+    pub kind: CommandKind,
+    pub uuid: CommandId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temp_id: Option<TempId>,
+}
+
+impl Command {
+    pub fn new(kind: CommandKind) -> Command {
+        Command {
+            kind: kind,
+            uuid: CommandId::new(),
+            temp_id: None,
+        }
+    }
+
+    /// A command that creates an object, which later commands can refer to by `temp_id`.
+    pub fn create(kind: CommandKind) -> Command {
+        Command {
+            kind: kind,
+            uuid: CommandId::new(),
+            temp_id: Some(TempId::new()),
+        }
+    }
+}
+
+fn list(ids: &[ObjectId]) -> String {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    format!("[{}]", ids.join(", "))
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            CommandKind::LabelAdd { ref name } => write!(f, "label_add '{}'", name)?,
+            CommandKind::ItemAdd { ref content, ref project_id, .. } => {
+                write!(f, "item_add '{}' to {}", content, project_id)?
+            }
+            CommandKind::ItemUpdate { ref id, ref labels } => {
+                write!(f, "item_update {}", id)?;
+                if let Some(ref labels) = *labels {
+                    write!(f, " labels={}", list(labels))?;
+                }
+            }
+            CommandKind::ItemMove { ref project_items, ref to_project } => {
+                let ids: Vec<ObjectId> = project_items.values().flat_map(|ids| ids.iter().cloned()).collect();
+                write!(f, "item_move {} to {}", list(&ids), to_project)?
+            }
+            CommandKind::ItemDelete { ref ids } => write!(f, "item_delete {}", list(ids))?,
+            CommandKind::CloseItem { ref id } => write!(f, "close_item {}", id)?,
+            CommandKind::ItemUncomplete { ref ids } => write!(f, "item_uncomplete {}", list(ids))?,
+            CommandKind::ItemUpdateOrdersIndents { ref ids_to_orders_indents } => {
+                let ids: Vec<ObjectId> = ids_to_orders_indents.keys().cloned().collect();
+                write!(f, "item_update_orders_indents {}", list(&ids))?
+            }
+            CommandKind::ProjectAdd { ref name, .. } => write!(f, "project_add '{}'", name)?,
+            CommandKind::ProjectUpdate { ref id, .. } => write!(f, "project_update {}", id)?,
+            CommandKind::ProjectArchive { ref ids } => write!(f, "project_archive {}", list(ids))?,
+            CommandKind::ProjectUpdateOrdersIndents { ref ids_to_orders_indents } => {
+                let ids: Vec<ObjectId> = ids_to_orders_indents.keys().cloned().collect();
+                write!(f, "project_update_orders_indents {}", list(&ids))?
+            }
+            CommandKind::NoteAdd { ref item_id, .. } => write!(f, "note_add on {}", item_id)?,
+        }
+        write!(f, " ({})", self.uuid)
+    }
+}
+
+/// What Todoist says about a command it couldn't carry out.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub error_code: i64,
+    pub error: String,
+    #[serde(default)]
+    pub http_code: Option<u16>,
+    #[serde(default)]
+    pub error_tag: Option<String>,
+}
+
+impl CommandError {
+    /// Whether sending the command again may work out: throttling and server trouble.
+    pub fn is_transient(&self) -> bool {
+        match self.http_code {
+            Some(429) => true,
+            Some(code) => code >= 500,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error {}: {}", self.error_code, self.error)?;
+        if let Some(code) = self.http_code {
+            write!(f, " (http {})", code)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of one command, `"ok"` or an error object on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandStatus {
+    Ok,
+    Failed(CommandError),
+}
+
+impl CommandStatus {
+    pub fn is_ok(&self) -> bool {
+        *self == CommandStatus::Ok
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawStatus {
+    Text(String),
+    Error(CommandError),
+}
+
+impl<'de> Deserialize<'de> for CommandStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        Ok(match RawStatus::deserialize(deserializer)? {
+            RawStatus::Text(ref text) if text == "ok" => CommandStatus::Ok,
+            RawStatus::Text(text) => {
+                CommandStatus::Failed(CommandError {
+                    error_code: 0,
+                    error: text,
+                    http_code: None,
+                    error_tag: None,
+                })
+            }
+            RawStatus::Error(err) => CommandStatus::Failed(err),
+        })
+    }
+}
@@ -0,0 +1,180 @@
+//! Drive several Todoist accounts from one process.
+//!
+//! Every account runs in a thread of its own, named after the account so that
+//! log lines can tell them apart. An account that fails, be it a revoked token
+//! or a bug, is restarted after a pause without holding up the others: a round
+//! that panics counts as a failed one, see `NextAction::loopit`.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use {NextAction, Todoist, ApiVersion, Backoff, Markers, Marker, Grouping, ArchiveRules, ReviewRules, Result,
+     MAX_INTERVAL, NEXTACTION, SOMEDAY};
+
+/// Where a webhook driven account listens, see `NextAction::serve`.
+#[derive(Clone)]
+pub struct WebhookSettings {
+    pub addr: String,
+    pub secret: String,
+    pub debounce: Duration,
+    pub fallback: Duration,
+}
+
+/// The settings of one account.
+#[derive(Clone)]
+pub struct Account {
+    /// Names the thread and the log lines of the account.
+    pub name: String,
+    pub token: String,
+    pub nextaction_name: String,
+    pub someday_name: String,
+    pub markers: Markers,
+    pub parallel_label: Option<String>,
+    pub sequential_label: Option<String>,
+    pub project_modes: BTreeMap<String, Grouping>,
+    pub on_hold_markers: Vec<Marker>,
+    pub on_hold_projects: Vec<String>,
+    pub complete_parallel: bool,
+    pub complete_sequential: bool,
+    pub archive_projects: bool,
+    /// Only used with `archive_projects`.
+    pub archive: ArchiveRules,
+    pub review_projects: bool,
+    /// Only used with `review_projects`.
+    pub review: ReviewRules,
+    /// Seconds between two rounds.
+    pub interval: u64,
+    pub incremental: bool,
+    pub state_dir: Option<PathBuf>,
+    pub api_version: ApiVersion,
+    pub batch_size: Option<usize>,
+    /// Follow webhook deliveries instead of polling every `interval`.
+    pub webhook: Option<WebhookSettings>,
+}
+
+impl Account {
+    pub fn new(name: &str, token: &str) -> Account {
+        Account {
+            name: name.into(),
+            token: token.into(),
+            nextaction_name: NEXTACTION.into(),
+            someday_name: SOMEDAY.into(),
+            markers: Markers::default(),
+            parallel_label: None,
+            sequential_label: None,
+            project_modes: BTreeMap::new(),
+            on_hold_markers: vec![],
+            on_hold_projects: vec![],
+            complete_parallel: false,
+            complete_sequential: false,
+            archive_projects: false,
+            archive: ArchiveRules::default(),
+            review_projects: false,
+            review: ReviewRules::default(),
+            interval: 10,
+            incremental: true,
+            state_dir: None,
+            api_version: ApiVersion::V7,
+            batch_size: None,
+            webhook: None,
+        }
+    }
+
+    /// A `NextAction` set up for this account.
+    pub fn next_action(&self) -> NextAction {
+        self.next_action_with(Todoist::new(&self.token))
+    }
+
+    /// A `NextAction` set up for this account on top of `todoist`.
+    pub fn next_action_with(&self, mut todoist: Todoist) -> NextAction {
+        todoist.set_api_version(self.api_version);
+        if let Some(batch_size) = self.batch_size {
+            todoist.set_batch_size(batch_size);
+        }
+        let mut na = NextAction::with_todoist(todoist);
+        na.nextaction_name = self.nextaction_name.clone();
+        na.someday_name = self.someday_name.clone();
+        na.markers = self.markers.clone();
+        na.parallel_label = self.parallel_label.clone();
+        na.sequential_label = self.sequential_label.clone();
+        na.project_modes = self.project_modes.clone();
+        na.on_hold_markers = self.on_hold_markers.clone();
+        na.on_hold_projects = self.on_hold_projects.clone();
+        na.complete_parallel = self.complete_parallel;
+        na.complete_sequential = self.complete_sequential;
+        if self.archive_projects {
+            na.archive = Some(self.archive.clone());
+        }
+        if self.review_projects {
+            na.review = Some(self.review.clone());
+        }
+        na.incremental = self.incremental;
+        // the saved ids only hold for the names above, so they go first
+        if let Some(ref dir) = self.state_dir {
+            na.set_state_dir(dir);
+        }
+        na
+    }
+
+    /// Run the rounds of this account until the process ends.
+    pub fn drive(&self) {
+        let mut na = self.next_action();
+        let mut backoff = Backoff::new(Duration::new(self.interval.max(1), 0),
+                                       Duration::new(MAX_INTERVAL, 0));
+        loop {
+            let started = Instant::now();
+            let result = match self.webhook {
+                Some(ref w) => na.serve(&w.addr, &w.secret, w.debounce, w.fallback),
+                None => na.loopit(self.interval),
+            };
+            if let Err(err) = result {
+                // a run that held up for a while doesn't count towards the backoff
+                if started.elapsed() > Duration::new(MAX_INTERVAL, 0) {
+                    backoff.reset();
+                }
+                let wait = backoff.fail(None);
+                error!("Unexpected error: '{:?}', restarting the loop in {:?}", err, wait);
+                sleep(wait);
+            }
+        }
+    }
+}
+
+/// Make sure no two webhook driven accounts listen on the same address, the
+/// second one could never bind it.
+pub fn check(accounts: &[Account]) -> Result<()> {
+    let mut listeners: BTreeMap<&str, &str> = BTreeMap::new();
+    for account in accounts {
+        if let Some(ref webhook) = account.webhook {
+            if let Some(other) = listeners.insert(&webhook.addr, &account.name) {
+                return Err(format!("Accounts '{}' and '{}' both listen for webhooks on {}, give each its own \
+                                    webhook_addr",
+                                   other,
+                                   account.name,
+                                   webhook.addr)
+                    .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drive every account in a thread of its own. Only returns once all of them
+/// are gone, which takes a panic outside a round in each.
+pub fn run(accounts: Vec<Account>) -> Result<()> {
+    check(&accounts)?;
+    let mut handles = vec![];
+    for account in accounts {
+        let name = account.name.clone();
+        let handle = thread::Builder::new().name(name.clone()).spawn(move || account.drive())?;
+        handles.push((name, handle));
+    }
+    for (name, handle) in handles {
+        if handle.join().is_err() {
+            error!("Account '{}' panicked and is stopped", name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Defer dates: a task with a start date isn't available before that day, the
+//! way OmniFocus' defer dates work.
+//!
+//! The start date is a token in the content of the task or in one of its
+//! notes, as in `Renew passport start:2026-11-01`. It counts from midnight in
+//! the user's time zone.
+
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+use protocol::TzInfo;
+
+/// The token introducing a start date.
+pub const START: &'static str = "start:";
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A calendar day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Day {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Day {
+    /// The day `secs` seconds after the epoch falls on, at `utc_offset`
+    /// seconds east of UTC.
+    pub fn at(secs: u64, utc_offset: i64) -> Day {
+        let days = floor_div(secs as i64 + utc_offset, SECS_PER_DAY);
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = floor_div(z, 146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        Day {
+            year: year,
+            month: month,
+            day: day,
+        }
+    }
+
+    /// The day `secs` seconds after the epoch falls on for a user in `tz`,
+    /// UTC when the time zone isn't known.
+    pub fn today(tz: Option<&TzInfo>, secs: u64) -> Day {
+        Day::at(secs, tz.map(utc_offset).unwrap_or(0))
+    }
+}
+
+/// Parse `YYYY-MM-DD`.
+impl FromStr for Day {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Day> {
+        let parts: Vec<&str> = s.split('-').collect();
+        let invalid = || -> Error { format!("'{}' is not a date, use YYYY-MM-DD", s).into() };
+        if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+            return Err(invalid());
+        }
+        let year: i64 = parts[0].parse().map_err(|_| invalid())?;
+        let month: u32 = parts[1].parse().map_err(|_| invalid())?;
+        let day: u32 = parts[2].parse().map_err(|_| invalid())?;
+        if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        Ok(Day {
+            year: year,
+            month: month,
+            day: day,
+        })
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+fn floor_div(a: i64, b: i64) -> i64 {
+    if a < 0 { (a - b + 1) / b } else { a / b }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Seconds east of UTC. Todoist gives `{"hours": -3, "minutes": 30}` for
+/// `-03:30`, so the sign goes with the hours or the GMT string.
+pub fn utc_offset(tz: &TzInfo) -> i64 {
+    let sign = if tz.hours < 0 || tz.gmt_string.starts_with('-') { -1 } else { 1 };
+    sign * (tz.hours.abs() * 60 * 60 + tz.minutes.abs() * 60)
+}
+
+/// The start date given in `text`, if any. A token that isn't a date is ignored.
+pub fn start_date(text: &str) -> Option<Day> {
+    text.split_whitespace()
+        .filter(|word| word.starts_with(START))
+        .filter_map(|word| word[START.len()..].parse().ok())
+        .next()
+}
@@ -0,0 +1,376 @@
+//! The decision engine: walks a `TaskTree` and says which labels should change,
+//! without knowing anything about how those changes reach Todoist.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use defer::{self, Day};
+use markers::{self, Markers, Marker, Grouping};
+use plan::Reason;
+use protocol::{CommandManager, Project, Item};
+use {TaskTree, Node, NodeType};
+
+/// What the engine works with besides the tree itself.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    pub nextaction_id: usize,
+    pub someday_id: usize,
+    pub markers: Markers,
+    /// Labels giving the grouping of a task whose name has no marker.
+    pub parallel_id: Option<usize>,
+    pub sequential_id: Option<usize>,
+    /// The grouping of projects whose name has no marker, keyed by project id
+    /// or name. Names are compared regardless of case.
+    pub project_modes: BTreeMap<String, Grouping>,
+    /// Projects and sections whose name carries one of these are on hold,
+    /// like a task labelled someday: nothing below them is a next action.
+    pub on_hold_markers: Vec<Marker>,
+    /// Projects on hold, by id or name regardless of case.
+    pub on_hold_projects: Vec<String>,
+    /// The day in the user's time zone. Without it start dates are ignored.
+    pub today: Option<Day>,
+    /// Start dates given in notes, by item id. One in the content goes first.
+    pub start_dates: BTreeMap<usize, Day>,
+    /// Close parallel or sequential tasks once all their subtasks are done.
+    pub complete_parallel: bool,
+    pub complete_sequential: bool,
+}
+
+impl Rules {
+    pub fn new(nextaction_id: usize, someday_id: usize) -> Rules {
+        Rules {
+            nextaction_id: nextaction_id,
+            someday_id: someday_id,
+            markers: Markers::default(),
+            parallel_id: None,
+            sequential_id: None,
+            project_modes: BTreeMap::new(),
+            on_hold_markers: vec![],
+            on_hold_projects: vec![],
+            today: None,
+            start_dates: BTreeMap::new(),
+            complete_parallel: false,
+            complete_sequential: false,
+        }
+    }
+
+    fn completes(&self, grouping: Grouping) -> bool {
+        match grouping {
+            Grouping::Parallel => self.complete_parallel,
+            Grouping::Sequential => self.complete_sequential,
+        }
+    }
+
+    /// How the children of `node` are worked on. A marker in the name goes
+    /// before a label or a configured project mode.
+    pub fn grouping(&self, node: &Node) -> Option<Grouping> {
+        self.markers.grouping(&node.name()).or_else(|| {
+            match node.ntype {
+                NodeType::ItemNodeType(ref item) => {
+                    let has = |id: Option<usize>| id.map(|id| item.labels.contains(&id)).unwrap_or(false);
+                    if has(self.parallel_id) {
+                        Some(Grouping::Parallel)
+                    } else if has(self.sequential_id) {
+                        Some(Grouping::Sequential)
+                    } else {
+                        None
+                    }
+                }
+                NodeType::ProjectNodeType(ref project) => self.project_mode(project),
+                NodeType::SectionNodeType(_) => None,
+            }
+        })
+    }
+
+    /// Whether `node` and everything below it is on hold.
+    pub fn is_on_hold(&self, node: &Node) -> bool {
+        let marked = || {
+            let name = markers::bare_name(&node.name());
+            self.on_hold_markers.iter().any(|m| m.matches(&name))
+        };
+        match node.ntype {
+            NodeType::ItemNodeType(ref item) => item.labels.contains(&self.someday_id),
+            NodeType::ProjectNodeType(ref project) => {
+                let (id, name) = (project.id.to_string(), project.name.to_lowercase());
+                marked() || self.on_hold_projects.iter().any(|key| *key == id || key.to_lowercase() == name)
+            }
+            NodeType::SectionNodeType(_) => marked(),
+        }
+    }
+
+    pub fn start_date(&self, item: &Item) -> Option<Day> {
+        defer::start_date(&item.content).or_else(|| self.start_dates.get(&item.id).cloned())
+    }
+
+    /// Whether `node` is a task that can't be started before a later day.
+    pub fn is_deferred(&self, node: &Node) -> bool {
+        match (&node.ntype, self.today) {
+            (&NodeType::ItemNodeType(ref item), Some(today)) => {
+                self.start_date(item).map(|start| start > today).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    fn project_mode(&self, project: &Project) -> Option<Grouping> {
+        let (id, name) = (project.id.to_string(), project.name.to_lowercase());
+        self.project_modes
+            .get(&id)
+            .or_else(|| {
+                self.project_modes
+                    .iter()
+                    .find(|&(key, _)| key.to_lowercase() == name)
+                    .map(|(_, mode)| mode)
+            })
+            .cloned()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LabelChange {
+    pub item_id: usize,
+    pub add: Vec<usize>,
+    pub remove: Vec<usize>,
+    pub reason: Reason,
+}
+
+impl LabelChange {
+    /// The labels an item ends up with, added labels go first.
+    pub fn apply(&self, labels: &[usize]) -> Vec<usize> {
+        let mut v = self.add.clone();
+        v.extend(labels.iter().filter(|l| !self.add.contains(l) && !self.remove.contains(l)));
+        v
+    }
+}
+
+/// A task to close because all its subtasks are done.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub item_id: usize,
+    pub grouping: Grouping,
+}
+
+impl TaskTree {
+    /// The label changes of a round. Tasks about to be closed count as done.
+    pub fn label_changes(&self, rules: &Rules) -> Vec<LabelChange> {
+        let done: BTreeSet<usize> = self.completions(rules).iter().map(|c| c.item_id).collect();
+        let mut changes = vec![];
+        for node in &self.nodes {
+            traversal(node, &mut changes, TraversalState::Unconstraint, rules, &done)
+        }
+        changes
+    }
+
+    /// The tasks to close, subtasks before their parents.
+    pub fn completions(&self, rules: &Rules) -> Vec<Completion> {
+        let mut completions = vec![];
+        for node in &self.nodes {
+            complete(node, rules, &mut completions);
+        }
+        completions
+    }
+}
+
+/// Turn completions into `close_item` commands.
+pub fn queue_completions(completions: &[Completion], manager: &mut CommandManager) {
+    for completion in completions {
+        manager.complete_item(completion.item_id);
+    }
+}
+
+/// Collect the tasks at and below `node` to close, and tell whether `node` is
+/// done, already or once they are closed.
+fn complete(node: &Node, rules: &Rules, completions: &mut Vec<Completion>) -> bool {
+    let mut subtasks_done = true;
+    for child in &node.nodes {
+        subtasks_done &= complete(child, rules, completions);
+    }
+    match node.ntype {
+        NodeType::ItemNodeType(ref item) => {
+            if item.checked == 1 {
+                return true;
+            }
+            // closing a recurring task only moves it on to its next date
+            if node.nodes.is_empty() || !subtasks_done || item.is_recurring() {
+                return false;
+            }
+            match rules.grouping(node) {
+                Some(grouping) if rules.completes(grouping) => {
+                    completions.push(Completion {
+                        item_id: item.id,
+                        grouping: grouping,
+                    });
+                    true
+                }
+                _ => false,
+            }
+        }
+        NodeType::ProjectNodeType(_) | NodeType::SectionNodeType(_) => false,
+    }
+}
+
+fn is_done(node: &Node, done: &BTreeSet<usize>) -> bool {
+    node.checked() || (node.is_item() && done.contains(&node.id()))
+}
+
+/// Whether any item at or below `node` stays open this round.
+fn is_open(node: &Node, done: &BTreeSet<usize>) -> bool {
+    (node.is_item() && !is_done(node, done)) || node.nodes.iter().any(|node| is_open(node, done))
+}
+
+/// Turn label changes into `item_update` commands.
+pub fn queue(tree: &TaskTree, changes: &[LabelChange], manager: &mut CommandManager) {
+    for change in changes {
+        match tree.item(change.item_id) {
+            Some(item) => {
+                manager.set_item_label(item.id, change.apply(&item.labels));
+            }
+            None => warn!("Item {} of a label change is not in the tree", change.item_id),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum TraversalState {
+    /// Below a task labelled someday or a project on hold.
+    OnHold,
+    /// At or below a task with a start date still to come.
+    Deferred,
+    Suppressed,
+    Unconstraint,
+    Active,
+}
+
+/// The children of `node`, with those of a section that has no marker of its
+/// own in place of the section, so that they go by the project's grouping.
+fn see_through<'a>(node: &'a Node, rules: &Rules) -> Vec<&'a Node> {
+    let mut children = vec![];
+    for child in &node.nodes {
+        match child.ntype {
+            NodeType::SectionNodeType(_) if rules.grouping(child).is_none() && !rules.is_on_hold(child) => {
+                children.extend(child.nodes.iter())
+            }
+            _ => children.push(child),
+        }
+    }
+    children
+}
+
+fn traversal(node: &Node,
+             changes: &mut Vec<LabelChange>,
+             state: TraversalState,
+             rules: &Rules,
+             done: &BTreeSet<usize>) {
+    use self::TraversalState::*;
+
+    // a deferred task and its subtasks aren't available, though still open
+    let state = if state != OnHold && !rules.is_on_hold(node) && rules.is_deferred(node) {
+        Deferred
+    } else {
+        state
+    };
+    let (naid, sdid) = (rules.nextaction_id, rules.someday_id);
+    let grouping = rules.grouping(node);
+
+    let (is_parallel, is_sequential) = (grouping == Some(Grouping::Parallel),
+                                        grouping == Some(Grouping::Sequential));
+
+    match node.ntype {
+        NodeType::ItemNodeType(ref rnode) => {
+            let mut change = |add: Vec<usize>, remove: Vec<usize>, reason: Reason| {
+                changes.push(LabelChange {
+                    item_id: rnode.id,
+                    add: add,
+                    remove: remove,
+                    reason: reason,
+                })
+            };
+
+            if is_done(node, done) {
+                if rnode.labels.contains(&naid) || rnode.labels.contains(&sdid) {
+                    let v: Vec<usize> = rnode.labels
+                        .iter()
+                        .cloned()
+                        .filter(|&u| u == naid || u == sdid)
+                        .collect();
+                    change(vec![], v, Reason::Completed);
+                }
+            } else {
+                if state == OnHold || state == Deferred {
+                    if rnode.labels.contains(&naid) {
+                        let reason = if state == OnHold { Reason::Someday } else { Reason::Deferred };
+                        change(vec![], vec![naid], reason);
+                    }
+                } else if state == Active &&
+                   (node.nodes.len() == 0 || node.nodes.iter().all(|l| is_done(l, done)) ||
+                    (!is_parallel && !is_sequential)) &&
+                   !rnode.labels.contains(&sdid) {
+                    if !rnode.labels.contains(&naid) {
+                        change(vec![naid], vec![], Reason::NextAction);
+                    }
+                } else {
+                    if rnode.labels.contains(&naid) {
+                        let reason = if rnode.labels.contains(&sdid) {
+                            Reason::Someday
+                        } else {
+                            match state {
+                                OnHold | Deferred | Suppressed => Reason::Blocked,
+                                Unconstraint => Reason::Ungrouped,
+                                Active => Reason::HasSubtasks,
+                            }
+                        };
+                        change(vec![], vec![naid], reason);
+                    }
+                }
+            }
+        }
+        NodeType::ProjectNodeType(_) | NodeType::SectionNodeType(_) => {}
+    }
+
+
+    let mut substate = match state {
+        Unconstraint => Active,
+        Suppressed => Suppressed,
+        Active => Active,
+        OnHold => OnHold,
+        Deferred => Deferred,
+    };
+
+    let children = see_through(node, rules);
+    if state == OnHold || rules.is_on_hold(node) {
+        for node in &children {
+            traversal(node, changes, OnHold, rules, done);
+        }
+    } else if state == Deferred {
+        for node in &children {
+            traversal(node, changes, Deferred, rules, done);
+        }
+    } else if is_parallel {
+        for node in &children {
+            traversal(node, changes, substate, rules, done);
+        }
+    } else if is_sequential {
+        for node in &children {
+            traversal(node, changes, substate, rules, done);
+            match node.ntype {
+                NodeType::ItemNodeType(_) => {
+                    if !is_done(node, done) {
+                        substate = Suppressed;
+                    }
+                }
+                NodeType::ProjectNodeType(_) => {
+                    substate = Suppressed;
+                }
+                NodeType::SectionNodeType(_) => {
+                    // a section holds up the rest until it is done
+                    if is_open(node, done) {
+                        substate = Suppressed;
+                    }
+                }
+            }
+        }
+    } else {
+        for node in &children {
+            traversal(node, changes, Unconstraint, rules, done);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use hyper::error::Error as HyperError;
 use std::io::Error as StdIOError;
+use std::time::Duration;
 use serde_json::Error as SerdeError;
 
 error_chain! {
@@ -16,5 +17,40 @@ error_chain! {
             description("internal error")
             display("Internal error: '{}'",  t)
         }
+        MissingProject(item_id: usize, project_id: usize) {
+            description("item of an unknown project")
+            display("Item {} belongs to project {}, which is unknown", item_id, project_id)
+        }
+        RateLimited(retry_after: Option<u64>) {
+            description("rate limited")
+            display("Rate limited by Todoist, retry after {:?} sec", retry_after)
+        }
+        ServerError(status: u16, retry_after: Option<u64>) {
+            description("server error")
+            display("Todoist answered with status {}", status)
+        }
+    }
+}
+
+impl Error {
+    /// Whether the request may go through when sent again later: throttling,
+    /// server trouble and network issues.
+    pub fn is_transient(&self) -> bool {
+        match *self.kind() {
+            ErrorKind::RateLimited(_) |
+            ErrorKind::ServerError(..) |
+            ErrorKind::HyperError(_) |
+            ErrorKind::IoError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// How long Todoist asked us to wait before the next request.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match *self.kind() {
+            ErrorKind::RateLimited(Some(secs)) |
+            ErrorKind::ServerError(_, Some(secs)) => Some(Duration::from_secs(secs)),
+            _ => None,
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,576 @@
+//! An in-process stand-in for the Todoist sync endpoint.
+//!
+//! It speaks the part of the v7 protocol this crate uses: `resource_types`
+//! syncs (full and incremental) and the commands `CommandManager` queues,
+//! from `label_add` to `note_add`. Plug it into `Todoist::with_transport`
+//! and the whole sync/flush path runs offline.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{from_str, to_value, Value as JsonValue, Map};
+
+use errors::*;
+use protocol::{Project, Item, Label, Note, User, TodoistResponse};
+use transport::{Transport, HttpRequest, HttpResponse};
+
+#[derive(Default)]
+struct World {
+    revision: usize,
+    last_id: usize,
+    last_order: usize,
+    user: Option<User>,
+    // every object carries the revision it was last touched in
+    projects: Vec<(usize, Project)>,
+    items: Vec<(usize, Item)>,
+    labels: Vec<(usize, Label)>,
+    notes: Vec<(usize, Note)>,
+    commands: Vec<JsonValue>,
+    requests: Vec<HttpRequest>,
+    // http codes of the transient failures the next commands run into
+    failures: Vec<u16>,
+    // requests with more commands than this are refused
+    command_limit: Option<usize>,
+}
+
+impl World {
+    fn next_id(&mut self) -> usize {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn next_order(&mut self) -> usize {
+        self.last_order += 1;
+        self.last_order
+    }
+
+    fn bump(&mut self) -> usize {
+        self.revision += 1;
+        self.revision
+    }
+
+    fn item_mut(&mut self, id: usize) -> Option<&mut (usize, Item)> {
+        self.items.iter_mut().find(|&&mut (_, ref item)| item.id == id)
+    }
+
+    fn project_mut(&mut self, id: usize) -> Option<&mut (usize, Project)> {
+        self.projects.iter_mut().find(|&&mut (_, ref project)| project.id == id)
+    }
+
+    fn sync(&self, sync_token: &str, resource_types: &[String], out: &mut Map<String, JsonValue>) {
+        let since = if sync_token == "*" {
+            None
+        } else {
+            sync_token.parse::<usize>().ok()
+        };
+        let wanted = |name: &str| resource_types.iter().any(|r| r == "all" || r == name);
+        let fresh = |rev: usize| since.map(|since| rev > since).unwrap_or(true);
+
+        // the items of archived projects are never sent, the projects themselves
+        // only show up in a delta
+        let archived = |project_id: usize| {
+            self.projects.iter().any(|&(_, ref p)| p.id == project_id && p.is_archived == 1)
+        };
+
+        if wanted("projects") {
+            let projects: Vec<&Project> = self.projects
+                .iter()
+                .filter(|&&(rev, ref p)| fresh(rev) && !(since.is_none() && archived(p.id)))
+                .map(|&(_, ref p)| p)
+                .collect();
+            out.insert("projects".into(), to_value(projects).unwrap());
+        }
+        if wanted("items") {
+            let items: Vec<&Item> = self.items
+                .iter()
+                .filter(|&&(rev, ref i)| fresh(rev) && !archived(i.project_id))
+                .map(|&(_, ref i)| i)
+                .collect();
+            out.insert("items".into(), to_value(items).unwrap());
+        }
+        if wanted("labels") {
+            let labels: Vec<&Label> =
+                self.labels.iter().filter(|&&(rev, _)| fresh(rev)).map(|&(_, ref l)| l).collect();
+            out.insert("labels".into(), to_value(labels).unwrap());
+        }
+        if wanted("notes") {
+            let notes: Vec<&Note> = self.notes
+                .iter()
+                .filter(|&&(rev, ref n)| fresh(rev) && !archived(n.project_id))
+                .map(|&(_, ref n)| n)
+                .collect();
+            out.insert("notes".into(), to_value(notes).unwrap());
+        }
+        if wanted("user") && since.is_none() {
+            if let Some(ref user) = self.user {
+                out.insert("user".into(), to_value(user).unwrap());
+            }
+        }
+        out.insert("full_sync".into(), json!(since.is_none()));
+        out.insert("sync_token".into(), json!(format!("{}", self.revision)));
+    }
+
+    fn execute(&mut self,
+               command: &JsonValue,
+               temp_ids: &BTreeMap<String, usize>)
+               -> ::std::result::Result<Option<usize>, String> {
+        let args = &command["args"];
+        // temp ids are only known within the request that created them
+        let resolve = |value: &JsonValue| {
+            value.as_u64()
+                .map(|id| id as usize)
+                .or_else(|| value.as_str().and_then(|temp_id| temp_ids.get(temp_id).cloned()))
+        };
+        let id = |key: &str| resolve(&args[key]).ok_or(format!("Missing argument '{}'", key));
+        let ids = |key: &str| {
+            args[key]
+                .as_array()
+                .map(|ids| ids.iter().filter_map(&resolve).collect::<Vec<usize>>())
+                .ok_or(format!("Missing argument '{}'", key))
+        };
+        // `{id: [item_order, indent]}`, keyed by real or temp ids
+        let orders_indents = |key: &str| {
+            args[key]
+                .as_object()
+                .map(|orders| {
+                    orders.iter()
+                        .filter_map(|(id, place)| {
+                            let id = id.parse().ok().or_else(|| temp_ids.get(id).cloned());
+                            let order = place[0].as_u64().map(|order| order as usize);
+                            let indent = place[1].as_u64().map(|indent| indent as usize);
+                            match (id, order, indent) {
+                                (Some(id), Some(order), Some(indent)) => Some((id, order, indent)),
+                                _ => None,
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .ok_or(format!("Missing argument '{}'", key))
+        };
+        let string = |key: &str| {
+            args[key].as_str().map(|s| s.to_string()).ok_or(format!("Missing argument '{}'", key))
+        };
+        let number = |key: &str| args[key].as_u64().map(|n| n as usize);
+        let rev = self.bump();
+
+        match command["type"].as_str().unwrap_or("") {
+            "label_add" => {
+                let name = string("name")?;
+                let id = self.next_id();
+                self.labels.push((rev,
+                                  Label {
+                                      id: id,
+                                      name: name,
+                                      ..Default::default()
+                                  }));
+                Ok(Some(id))
+            }
+            "item_add" => {
+                let content = string("content")?;
+                let project_id = id("project_id")?;
+                self.project_mut(project_id).ok_or("Project not found".to_string())?;
+                let order = match number("item_order") {
+                    Some(order) => order,
+                    None => self.next_order(),
+                };
+                let id = self.next_id();
+                self.items.push((rev,
+                                 Item {
+                                     id: id,
+                                     project_id: project_id,
+                                     content: content,
+                                     parent_id: resolve(&args["parent_id"]),
+                                     indent: number("indent").unwrap_or(1),
+                                     item_order: order,
+                                     priority: number("priority").unwrap_or(1),
+                                     labels: ids("labels").unwrap_or_default(),
+                                     date_string: args["date_string"].as_str().map(|s| s.into()),
+                                     ..Default::default()
+                                 }));
+                Ok(Some(id))
+            }
+            "item_update" => {
+                let item = self.item_mut(id("id")?).ok_or("Item not found".to_string())?;
+                item.0 = rev;
+                if let Some(labels) = args["labels"].as_array() {
+                    item.1.labels = labels.iter().filter_map(&resolve).collect();
+                }
+                if let Some(content) = args["content"].as_str() {
+                    item.1.content = content.into();
+                }
+                Ok(None)
+            }
+            "item_move" => {
+                let to_project = id("to_project")?;
+                self.project_mut(to_project).ok_or("Project not found".to_string())?;
+                let moved: Vec<usize> = args["project_items"]
+                    .as_object()
+                    .ok_or("Missing argument 'project_items'".to_string())?
+                    .values()
+                    .filter_map(|ids| ids.as_array())
+                    .flat_map(|ids| ids.iter().filter_map(&resolve))
+                    .collect();
+                for id in moved {
+                    let item = self.item_mut(id).ok_or("Item not found".to_string())?;
+                    item.0 = rev;
+                    item.1.project_id = to_project;
+                    item.1.parent_id = None;
+                }
+                Ok(None)
+            }
+            "item_delete" => {
+                for id in ids("ids")? {
+                    let item = self.item_mut(id).ok_or("Item not found".to_string())?;
+                    item.0 = rev;
+                    item.1.is_deleted = 1;
+                }
+                Ok(None)
+            }
+            "close_item" => {
+                let item = self.item_mut(id("id")?).ok_or("Item not found".to_string())?;
+                item.0 = rev;
+                item.1.checked = 1;
+                Ok(None)
+            }
+            "item_uncomplete" => {
+                for id in ids("ids")? {
+                    let item = self.item_mut(id).ok_or("Item not found".to_string())?;
+                    item.0 = rev;
+                    item.1.checked = 0;
+                    item.1.in_history = 0;
+                }
+                Ok(None)
+            }
+            "item_update_orders_indents" => {
+                for (id, order, indent) in orders_indents("ids_to_orders_indents")? {
+                    let item = self.item_mut(id).ok_or("Item not found".to_string())?;
+                    item.0 = rev;
+                    item.1.item_order = order;
+                    item.1.indent = indent;
+                }
+                Ok(None)
+            }
+            "project_add" => {
+                let name = string("name")?;
+                let order = match number("item_order") {
+                    Some(order) => order,
+                    None => self.next_order(),
+                };
+                let id = self.next_id();
+                self.projects.push((rev,
+                                    Project {
+                                        id: id,
+                                        name: name,
+                                        parent_id: resolve(&args["parent_id"]),
+                                        indent: number("indent").unwrap_or(1),
+                                        item_order: order,
+                                        ..Default::default()
+                                    }));
+                Ok(Some(id))
+            }
+            "project_update" => {
+                let project = self.project_mut(id("id")?).ok_or("Project not found".to_string())?;
+                project.0 = rev;
+                if let Some(name) = args["name"].as_str() {
+                    project.1.name = name.into();
+                }
+                if let Some(collapsed) = number("collapsed") {
+                    project.1.collapsed = collapsed;
+                }
+                Ok(None)
+            }
+            "project_archive" => {
+                for id in ids("ids")? {
+                    let project = self.project_mut(id).ok_or("Project not found".to_string())?;
+                    project.0 = rev;
+                    project.1.is_archived = 1;
+                }
+                Ok(None)
+            }
+            "project_update_orders_indents" => {
+                for (id, order, indent) in orders_indents("ids_to_orders_indents")? {
+                    let project = self.project_mut(id).ok_or("Project not found".to_string())?;
+                    project.0 = rev;
+                    project.1.item_order = order;
+                    project.1.indent = indent;
+                }
+                Ok(None)
+            }
+            "note_add" => {
+                let content = string("content")?;
+                let item_id = id("item_id")?;
+                let project_id = self.item_mut(item_id).ok_or("Item not found".to_string())?.1.project_id;
+                let id = self.next_id();
+                self.notes.push((rev,
+                                 Note {
+                                     id: id,
+                                     item_id: item_id,
+                                     project_id: project_id,
+                                     content: content,
+                                     ..Default::default()
+                                 }));
+                Ok(Some(id))
+            }
+            other => Err(format!("Unknown command type '{}'", other)),
+        }
+    }
+
+    fn commands(&mut self, commands: Vec<JsonValue>, out: &mut Map<String, JsonValue>) {
+        let mut sync_status = Map::new();
+        let mut temp_id_mapping = Map::new();
+        let mut temp_ids = BTreeMap::new();
+
+        for command in commands {
+            let uuid = command["uuid"].as_str().unwrap_or("").to_string();
+            if !self.failures.is_empty() {
+                let code = self.failures.remove(0);
+                sync_status.insert(uuid,
+                                   json!({"error_code": 42,
+                                          "error": "Service temporarily unavailable",
+                                          "http_code": code}));
+                self.commands.push(command);
+                continue;
+            }
+            match self.execute(&command, &temp_ids) {
+                Ok(created) => {
+                    if let (Some(id), Some(temp_id)) = (created, command["temp_id"].as_str()) {
+                        temp_ids.insert(temp_id.to_string(), id);
+                        temp_id_mapping.insert(temp_id.into(), json!(id));
+                    }
+                    sync_status.insert(uuid, json!("ok"));
+                }
+                Err(error) => {
+                    sync_status.insert(uuid, json!({"error_code": 20, "error": error}));
+                }
+            }
+            self.commands.push(command);
+        }
+
+        out.insert("sync_status".into(), JsonValue::Object(sync_status));
+        out.insert("temp_id_mapping".into(), JsonValue::Object(temp_id_mapping));
+    }
+}
+
+/// A fake Todoist account kept in memory. Clones share the same account, so a
+/// test can hand one to `Todoist::with_transport` and inspect it through another.
+#[derive(Clone, Default)]
+pub struct FakeTodoist {
+    world: Arc<Mutex<World>>,
+}
+
+impl FakeTodoist {
+    pub fn new() -> FakeTodoist {
+        FakeTodoist::default()
+    }
+
+    /// Seed the account with a sync dump such as `format.json`.
+    pub fn from_response(response: &TodoistResponse) -> FakeTodoist {
+        let fake = FakeTodoist::new();
+        {
+            let mut world = fake.world.lock().unwrap();
+            world.user = response.user.clone();
+            for project in response.projects.iter().flat_map(|p| p.iter()) {
+                world.last_id = world.last_id.max(project.id);
+                world.last_order = world.last_order.max(project.item_order);
+                world.projects.push((0, project.clone()));
+            }
+            for item in response.items.iter().flat_map(|i| i.iter()) {
+                world.last_id = world.last_id.max(item.id);
+                world.last_order = world.last_order.max(item.item_order);
+                world.items.push((0, item.clone()));
+            }
+            for label in response.labels.iter().flat_map(|l| l.iter()) {
+                world.last_id = world.last_id.max(label.id);
+                world.labels.push((0, label.clone()));
+            }
+            for note in response.notes.iter().flat_map(|n| n.iter()) {
+                world.last_id = world.last_id.max(note.id);
+                world.notes.push((0, note.clone()));
+            }
+        }
+        fake
+    }
+
+    pub fn from_json(dump: &str) -> Result<FakeTodoist> {
+        let response: TodoistResponse = from_str(dump)?;
+        Ok(FakeTodoist::from_response(&response))
+    }
+
+    pub fn add_label(&self, name: &str) -> usize {
+        let mut world = self.world.lock().unwrap();
+        let (id, rev) = (world.next_id(), world.bump());
+        world.labels.push((rev,
+                           Label {
+                               id: id,
+                               name: name.into(),
+                               ..Default::default()
+                           }));
+        id
+    }
+
+    pub fn add_project(&self, name: &str, indent: usize) -> usize {
+        let mut world = self.world.lock().unwrap();
+        let (id, order, rev) = (world.next_id(), world.next_order(), world.bump());
+        world.projects.push((rev,
+                             Project {
+                                 id: id,
+                                 name: name.into(),
+                                 item_order: order,
+                                 indent: indent,
+                                 ..Default::default()
+                             }));
+        id
+    }
+
+    pub fn add_item(&self, project_id: usize, content: &str, indent: usize) -> usize {
+        let mut world = self.world.lock().unwrap();
+        let (id, order, rev) = (world.next_id(), world.next_order(), world.bump());
+        world.items.push((rev,
+                          Item {
+                              id: id,
+                              project_id: project_id,
+                              content: content.into(),
+                              indent: indent,
+                              item_order: order,
+                              priority: 1,
+                              ..Default::default()
+                          }));
+        id
+    }
+
+    /// Comment on an item, as the user would in the app.
+    pub fn add_note(&self, item_id: usize, content: &str) -> usize {
+        let mut world = self.world.lock().unwrap();
+        let (id, rev) = (world.next_id(), world.bump());
+        let project_id = world.item_mut(item_id).map(|item| item.1.project_id).unwrap_or_default();
+        world.notes.push((rev,
+                          Note {
+                              id: id,
+                              item_id: item_id,
+                              project_id: project_id,
+                              content: content.into(),
+                              ..Default::default()
+                          }));
+        id
+    }
+
+    /// The user sent along with a full sync.
+    pub fn set_user(&self, user: User) {
+        self.world.lock().unwrap().user = Some(user);
+    }
+
+    /// Tick an item off, as the user would in the app.
+    pub fn complete_item(&self, id: usize) {
+        let mut world = self.world.lock().unwrap();
+        let rev = world.bump();
+        if let Some(item) = world.item_mut(id) {
+            item.0 = rev;
+            item.1.checked = 1;
+        }
+    }
+
+    pub fn set_labels(&self, id: usize, labels: Vec<usize>) {
+        let mut world = self.world.lock().unwrap();
+        let rev = world.bump();
+        if let Some(item) = world.item_mut(id) {
+            item.0 = rev;
+            item.1.labels = labels;
+        }
+    }
+
+    pub fn item(&self, id: usize) -> Option<Item> {
+        let world = self.world.lock().unwrap();
+        world.items.iter().find(|&&(_, ref item)| item.id == id).map(|&(_, ref item)| item.clone())
+    }
+
+    pub fn project(&self, id: usize) -> Option<Project> {
+        let world = self.world.lock().unwrap();
+        world.projects
+            .iter()
+            .find(|&&(_, ref project)| project.id == id)
+            .map(|&(_, ref project)| project.clone())
+    }
+
+    /// The notes on an item, oldest first.
+    pub fn notes(&self, item_id: usize) -> Vec<Note> {
+        let world = self.world.lock().unwrap();
+        world.notes
+            .iter()
+            .filter(|&&(_, ref note)| note.item_id == item_id)
+            .map(|&(_, ref note)| note.clone())
+            .collect()
+    }
+
+    /// The names of the labels the server holds for an item, in the item's order.
+    pub fn label_names(&self, item_id: usize) -> Vec<String> {
+        let world = self.world.lock().unwrap();
+        let item = match world.items.iter().find(|&&(_, ref item)| item.id == item_id) {
+            Some(&(_, ref item)) => item,
+            None => return vec![],
+        };
+        item.labels
+            .iter()
+            .filter_map(|id| world.labels.iter().find(|&&(_, ref l)| l.id == *id))
+            .map(|&(_, ref l)| l.name.clone())
+            .collect()
+    }
+
+    /// Let the next `count` commands fail with a transient error carrying `http_code`.
+    pub fn fail_next_commands(&self, count: usize, http_code: u16) {
+        let mut world = self.world.lock().unwrap();
+        for _ in 0..count {
+            world.failures.push(http_code);
+        }
+    }
+
+    /// Refuse requests carrying more than `limit` commands, as Todoist does.
+    pub fn set_command_limit(&self, limit: usize) {
+        self.world.lock().unwrap().command_limit = Some(limit);
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.world.lock().unwrap().requests.clone()
+    }
+
+    /// Archive a project, as the user would in the app.
+    pub fn archive_project(&self, id: usize) {
+        let mut world = self.world.lock().unwrap();
+        let rev = world.bump();
+        if let Some(project) = world.project_mut(id) {
+            project.0 = rev;
+            project.1.is_archived = 1;
+        }
+    }
+
+    /// Every command received so far, in the order they were executed.
+    pub fn commands(&self) -> Vec<JsonValue> {
+        self.world.lock().unwrap().commands.clone()
+    }
+}
+
+impl Transport for FakeTodoist {
+    fn post(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let mut world = self.world.lock().unwrap();
+        let mut out = Map::new();
+        world.requests.push(request.clone());
+
+        if let Some(commands) = request.param("commands") {
+            let commands: Vec<JsonValue> = from_str(commands)?;
+            if world.command_limit.map(|limit| commands.len() > limit).unwrap_or(false) {
+                return Ok(HttpResponse {
+                    status: 400,
+                    headers: vec![],
+                    body: r#"{"error": "Too many commands"}"#.into(),
+                });
+            }
+            world.commands(commands, &mut out);
+        }
+        if let Some(resource_types) = request.param("resource_types") {
+            let resource_types: Vec<String> = from_str(resource_types)?;
+            world.sync(request.param("sync_token").unwrap_or("*"), &resource_types, &mut out);
+        }
+
+        Ok(HttpResponse::ok(JsonValue::Object(out).to_string()))
+    }
+}
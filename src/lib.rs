@@ -16,26 +16,72 @@ extern crate url;
 #[macro_use]
 extern crate mime;
 extern crate uuid;
+extern crate rand;
+extern crate hmac;
+extern crate sha2;
+extern crate base64;
 #[macro_use]
 extern crate error_chain;
 
+pub mod archive;
+pub mod backoff;
+pub mod command;
+pub mod daemon;
+pub mod defer;
+pub mod engine;
 pub mod errors;
+pub mod fake;
+pub mod markers;
+mod plan;
 mod protocol;
 mod rebuild_insert;
-
-use std::collections::BTreeSet;
+pub mod review;
+pub mod state;
+pub mod transport;
+mod v9;
+pub mod webhook;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::mpsc::channel;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::server::Server;
 
-pub use protocol::{Project, Item, CommandManager};
+pub use protocol::{Project, Section, Item, CommandManager};
+pub use protocol::{Label, Note, ProjectNote, FileAttachment, User, TzInfo, Filter, Reminder, Collaborator,
+                   CollaboratorState, Location, LiveNotification, Extra};
 pub use errors::*;
-pub use protocol::{Todoist, TodoistResponse};
+pub use protocol::{Todoist, TodoistResponse, CommandResponse, ApiVersion};
+pub use v9::IdMap;
 pub use rebuild_insert::RebuildInsertion;
+pub use backoff::Backoff;
+pub use archive::{ArchiveRules, ArchiveWatch};
+pub use review::{ReviewRules, ReviewWatch, Review};
+pub use defer::Day;
+pub use transport::{Transport, HyperTransport, MemoryTransport};
+pub use fake::FakeTodoist;
+pub use state::{StateStore, SyncState};
+pub use plan::{Plan, PlannedChange, PlannedCompletion, PlannedArchive, PlannedReview, Reason};
+pub use engine::{Rules, LabelChange, Completion};
+pub use markers::{Markers, Marker, Grouping};
+pub use command::{Command, CommandKind, CommandId, TempId, ObjectId, CommandStatus, CommandError};
+pub use webhook::{WebhookHandler, Delivery, Wakeup};
+pub use daemon::{Account, WebhookSettings};
 
 pub const NEXTACTION: &'static str = "NextAction";
 pub const SOMEDAY: &'static str = "Someday";
+/// The suffixes marking parallel and sequential names unless `Markers` say otherwise.
 pub const PARALLEL: char = '-';
 pub const SEQUENTIAL: char = ':';
+/// The longest pause between two rounds while Todoist keeps failing, in seconds.
+pub const MAX_INTERVAL: u64 = 30 * 60;
+
+// Stand-ins for labels a dry run would have created.
+const NEW_NEXTACTION_ID: usize = 0;
+const NEW_SOMEDAY_ID: usize = ::std::usize::MAX;
 
 pub struct NextAction {
     todoist: Todoist,
@@ -43,199 +89,452 @@ pub struct NextAction {
     tree: TaskTree,
     nextaction_id: Option<usize>,
     someday_id: Option<usize>,
+    parallel_id: Option<usize>,
+    sequential_id: Option<usize>,
+    /// The user's time zone, start dates count from midnight there.
+    tz_info: Option<TzInfo>,
+    /// The day, there, `tz_info` was last read on.
+    tz_day: Option<Day>,
     pub nextaction_name: String,
     pub someday_name: String,
+    /// What makes a name parallel or sequential.
+    pub markers: Markers,
+    /// Labels that make a task parallel or sequential, next to the markers.
+    /// They are created like the next action label once they are set.
+    pub parallel_label: Option<String>,
+    pub sequential_label: Option<String>,
+    /// Parallel or sequential projects by id or name, see `Rules::project_modes`.
+    pub project_modes: BTreeMap<String, Grouping>,
+    /// Projects on hold, see `Rules::on_hold_markers` and `Rules::on_hold_projects`.
+    pub on_hold_markers: Vec<Marker>,
+    pub on_hold_projects: Vec<String>,
+    /// Close parallel or sequential tasks once all their subtasks are done.
+    pub complete_parallel: bool,
+    pub complete_sequential: bool,
+    /// Archive finished projects, see `ArchiveRules`.
+    pub archive: Option<ArchiveRules>,
+    archive_watch: ArchiveWatch,
+    /// Add review tasks to projects, see `ReviewRules`.
+    pub review: Option<ReviewRules>,
+    review_watch: ReviewWatch,
+    /// Keep the model between rounds and only ask Todoist for what changed.
+    pub incremental: bool,
+    /// Work out the plan of each round but never send it.
+    pub dry_run: bool,
+    state: Option<StateStore>,
 }
 
 impl NextAction {
     pub fn new(token: &str) -> Self {
+        NextAction::with_todoist(Todoist::new(token))
+    }
+
+    pub fn with_todoist(todoist: Todoist) -> Self {
         NextAction {
-            todoist: Todoist::new(token),
+            todoist: todoist,
             tree: TaskTree::new(),
             bag: BagOfThings::default(),
             nextaction_id: None,
             nextaction_name: NEXTACTION.into(),
             someday_id: None,
             someday_name: SOMEDAY.into(),
+            markers: Markers::default(),
+            parallel_id: None,
+            parallel_label: None,
+            sequential_id: None,
+            sequential_label: None,
+            tz_info: None,
+            tz_day: None,
+            project_modes: BTreeMap::new(),
+            on_hold_markers: vec![],
+            on_hold_projects: vec![],
+            complete_parallel: false,
+            complete_sequential: false,
+            archive: None,
+            archive_watch: ArchiveWatch::default(),
+            review: None,
+            review_watch: ReviewWatch::default(),
+            incremental: true,
+            dry_run: false,
+            state: None,
+        }
+    }
+
+    /// Keep the sync state under `dir` and resume from whatever is already there.
+    pub fn set_state_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        let store = StateStore::new(dir);
+        if let Some(state) = store.load() {
+            self.restore(state);
+        }
+        // the reviews also survive a state that was thrown away
+        if let Some(reviews) = store.load_reviews() {
+            self.review_watch = ReviewWatch::restore(reviews, self.todoist.ids_mut());
+        }
+        self.state = Some(store);
+    }
+
+    fn restore(&mut self, state: SyncState) {
+        if state.api_version != self.todoist.api_version() {
+            warn!("State was synced with {:?}, starting over", state.api_version);
+            return;
+        }
+        self.todoist.restore_ids(state.ids);
+        self.bag = BagOfThings::default();
+        self.bag.projects.extend(state.projects);
+        self.bag.sections.extend(state.sections.into_iter().map(|s| (s.id, s)));
+        self.bag.items.extend(state.items);
+        self.bag.notes.extend(state.notes.into_iter().map(|n| (n.id, n)));
+        self.tz_info = state.tz_info;
+        self.todoist.set_sync_token(&state.sync_token);
+        // the ids only hold for the names they were resolved with, and an
+        // incremental sync won't list a label that already exists under a new
+        // name, so look them all up again rather than create it a second time
+        if state.nextaction_name != self.nextaction_name || state.someday_name != self.someday_name ||
+           state.parallel_label != self.parallel_label || state.sequential_label != self.sequential_label {
+            info!("Labels changed since the state was saved, syncing everything");
+            self.todoist.set_sync_token("*");
+        } else {
+            self.nextaction_id = state.nextaction_id;
+            self.someday_id = state.someday_id;
+            self.parallel_id = state.parallel_id;
+            self.sequential_id = state.sequential_id;
+        }
+        self.archive_watch = state.archive_watch;
+    }
+
+    fn save_state(&self) {
+        if let Some(ref store) = self.state {
+            let state = SyncState {
+                version: state::STATE_VERSION,
+                api_version: self.todoist.api_version(),
+                ids: self.todoist.ids().clone(),
+                sync_token: self.todoist.sync_token().into(),
+                projects: self.bag.projects.iter().cloned().collect(),
+                sections: self.bag.sections.values().cloned().collect(),
+                items: self.bag.items.iter().cloned().collect(),
+                notes: self.bag.notes.values().cloned().collect(),
+                tz_info: self.tz_info.clone(),
+                nextaction_name: self.nextaction_name.clone(),
+                nextaction_id: self.nextaction_id,
+                someday_name: self.someday_name.clone(),
+                someday_id: self.someday_id,
+                parallel_label: self.parallel_label.clone(),
+                parallel_id: self.parallel_id,
+                sequential_label: self.sequential_label.clone(),
+                sequential_id: self.sequential_id,
+                archive_watch: self.archive_watch.clone(),
+            };
+            if let Err(err) = store.save(&state) {
+                warn!("Cannot save state: '{}'", err);
+            }
+        }
+    }
+
+    fn save_reviews(&self) {
+        if let Some(ref store) = self.state {
+            if let Err(err) = store.save_reviews(&self.review_watch.saved(self.todoist.ids())) {
+                warn!("Cannot save reviews: '{}'", err);
+            }
         }
     }
 
     pub fn sync(&mut self) -> Result<()> {
+        if !self.incremental {
+            self.todoist.set_sync_token("*");
+        }
         let result = self.todoist.sync()?;
         debug!("Sync result: '{:?}'", result);
-        self.todoist.set_sync_token("*");
-        self.bag = BagOfThings::default();
-        self.bag.merge(&result);
+        if !self.incremental || result.is_full_sync() {
+            self.bag = BagOfThings::default();
+            self.nextaction_id = None;
+            self.someday_id = None;
+            self.parallel_id = None;
+            self.sequential_id = None;
+        }
+        self.merge(&result);
         debug!("Current Bag is '{:?}'", &self.bag);
-
-        // Find the nextaction lable id
-        if let Some(lb) = result.labels
-            .as_ref()
-            .unwrap()
-            .iter()
-            .find(|l| l.name == self.nextaction_name) {
-            self.nextaction_id = Some(lb.id);
+        // an incremental sync only carries a user that changed, and a switch to or
+        // from daylight saving time isn't one, so the time zone is read once a day
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let today = Day::today(self.tz_info.as_ref(), now);
+        if result.is_full_sync() {
+            self.tz_day = Some(today);
+        } else if self.tz_day != Some(today) && self.bag.has_start_dates() {
+            if let Some(user) = self.todoist.user()? {
+                self.tz_info = user.tz_info;
+            }
+            self.tz_day = Some(Day::today(self.tz_info.as_ref(), now));
         }
+
         // if not found, create a new lable with the name
-        if self.nextaction_id.is_none() {
+        if self.nextaction_id.is_none() && !self.dry_run {
             let lb = self.todoist.add_label(&self.nextaction_name)?;
             self.nextaction_id = Some(lb.id);
         }
-        // find the someday label id
-        if let Some(lb) = result.labels
-            .as_ref()
-            .unwrap()
-            .iter()
-            .find(|l| l.name == self.someday_name) {
-            self.someday_id = Some(lb.id);
-        }
         // if not found, create a new label with the name
-        if self.someday_id.is_none() {
+        if self.someday_id.is_none() && !self.dry_run {
             let lb = self.todoist.add_label(&self.someday_name)?;
             self.someday_id = Some(lb.id);
         }
+        // the grouping labels only once they are configured
+        if let (&Some(ref name), None, false) = (&self.parallel_label, self.parallel_id, self.dry_run) {
+            let lb = self.todoist.add_label(name)?;
+            self.parallel_id = Some(lb.id);
+        }
+        if let (&Some(ref name), None, false) = (&self.sequential_label, self.sequential_id, self.dry_run) {
+            let lb = self.todoist.add_label(name)?;
+            self.sequential_id = Some(lb.id);
+        }
 
+        self.save_state();
         Ok(())
     }
 
-    pub fn build_tree(&mut self) -> Result<()> {
-        self.tree = TaskTree::new();
+    /// Merge a sync result into the local model and pick up the label ids it carries,
+    /// without talking to Todoist.
+    pub fn merge(&mut self, result: &TodoistResponse) {
+        self.bag.merge(result);
 
-        for project in &self.bag.projects {
-            push_level(&mut self.tree.nodes,
-                       NodeType::ProjectNodeType(project.clone()),
-                       project.indent);
+        // Find the nextaction lable id, a deleted one has to be created again
+        if let Some(lb) = result.get_label_by_name(&self.nextaction_name) {
+            self.nextaction_id = if lb.is_deleted == 0 { Some(lb.id) } else { None };
+        }
+        // find the someday label id
+        if let Some(lb) = result.get_label_by_name(&self.someday_name) {
+            self.someday_id = if lb.is_deleted == 0 { Some(lb.id) } else { None };
+        }
+        if let Some(lb) = self.parallel_label.as_ref().and_then(|name| result.get_label_by_name(name)) {
+            self.parallel_id = if lb.is_deleted == 0 { Some(lb.id) } else { None };
+        }
+        if let Some(lb) = self.sequential_label.as_ref().and_then(|name| result.get_label_by_name(name)) {
+            self.sequential_id = if lb.is_deleted == 0 { Some(lb.id) } else { None };
         }
+        // an incremental sync only comes with a user that changed
+        if let Some(ref user) = result.user {
+            self.tz_info = user.tz_info.clone();
+        }
+    }
 
-        for item in &self.bag.items {
-            let project = self.tree
-                .search_project(item.project_id)
-                .ok_or("project_id not found in project".to_string())?;
+    /// Throw the local model away, the next `sync` downloads the whole account again.
+    pub fn reset(&mut self) {
+        self.todoist.set_sync_token("*");
+        self.bag = BagOfThings::default();
+    }
 
-            push_level(&mut project.nodes,
-                       NodeType::ItemNodeType(item.clone()),
-                       item.indent);
-        }
+    pub fn nextaction_id(&self) -> Option<usize> {
+        self.nextaction_id
+    }
+
+    pub fn someday_id(&self) -> Option<usize> {
+        self.someday_id
+    }
+
+    pub fn build_tree(&mut self) -> Result<()> {
+        self.tree = TaskTree::build_with_sections(&self.bag.projects, self.bag.sections.values(), &self.bag.items)?;
         debug!("Tree is {:?}", self.tree);
         Ok(())
     }
 
-    pub fn step(&mut self) -> Result<()> {
+    pub fn tree(&self) -> &TaskTree {
+        &self.tree
+    }
+
+    /// The rules the engine runs with, as far as they are known after the last sync.
+    pub fn rules(&self) -> Result<Rules> {
+        let mut rules = if self.dry_run {
+            Rules::new(self.nextaction_id.unwrap_or(NEW_NEXTACTION_ID),
+                       self.someday_id.unwrap_or(NEW_SOMEDAY_ID))
+        } else {
+            Rules::new(self.nextaction_id.ok_or("nextaction_id is None".to_string())?,
+                       self.someday_id.ok_or("someday_id is None".to_string())?)
+        };
+        rules.markers = self.markers.clone();
+        rules.parallel_id = self.parallel_id;
+        rules.sequential_id = self.sequential_id;
+        rules.project_modes = self.project_modes.clone();
+        rules.on_hold_markers = self.on_hold_markers.clone();
+        rules.on_hold_projects = self.on_hold_projects.clone();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        rules.today = Some(Day::today(self.tz_info.as_ref(), now));
+        rules.start_dates = self.bag.start_dates();
+        rules.complete_parallel = self.complete_parallel;
+        rules.complete_sequential = self.complete_sequential;
+        Ok(rules)
+    }
+
+    /// Run one round and return what it changed, or in dry run mode, what it would change.
+    pub fn step(&mut self) -> Result<Plan> {
         info!("Step a round");
         self.sync()?;
-        self.build_tree()?;
-        let mut m = self.todoist.manager();
-        for node in &self.tree.nodes {
-            traversal(node,
-                      &mut m,
-                      TraversalState::Unconstraint,
-                      self.nextaction_id.ok_or("nextaction_id is None".to_string())?,
-                      self.someday_id.ok_or("nextaction_id is None".to_string())?)
-        }
-        m.flush()?;
+        if let Err(err) = self.build_tree() {
+            if !self.incremental {
+                return Err(err);
+            }
+            warn!("Local model is inconsistent ('{}'), falling back to a full sync", err);
+            self.reset();
+            self.sync()?;
+            self.build_tree()?;
+        }
+        let rules = self.rules()?;
+        let changes = self.tree.label_changes(&rules);
+        let completions = self.tree.completions(&rules);
+        let mut plan = Plan::new(&self.tree, &changes, &completions);
+        for completion in &plan.completions {
+            info!("Closing {} '{}', all its subtasks are done", completion.item_id, completion.content);
+        }
+        plan.label_names.insert(rules.nextaction_id, self.nextaction_name.clone());
+        plan.label_names.insert(rules.someday_id, self.someday_name.clone());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        // a dry run works on a copy, so that it starts no clocks of its own
+        let mut archive_watch = self.archive_watch.clone();
+        let archives = match self.archive {
+            Some(ref rules) => archive_watch.update(&self.tree, rules, now),
+            None => vec![],
+        };
+        plan.add_archives(&self.tree, &archives);
+        for archive in &plan.archives {
+            info!("Archiving project {} '{}', all its tasks are done", archive.project_id, archive.name);
+        }
+        let mut review_watch = self.review_watch.clone();
+        let reviews: Vec<usize> = match self.review {
+            Some(ref rules) => {
+                review_watch.update(&self.tree, rules, now)
+                    .into_iter()
+                    .filter(|id| !archives.contains(id))
+                    .collect()
+            }
+            None => vec![],
+        };
+        plan.add_reviews(&self.tree, &reviews);
+        for review in &plan.reviews {
+            info!("Project {} '{}' is due for a review", review.project_id, review.name);
+        }
+        if !self.dry_run {
+            self.archive_watch = archive_watch;
+            self.review_watch = review_watch;
+        }
+        if !self.dry_run && self.archive.is_some() {
+            self.save_state();
+        }
+        if !self.dry_run && self.review.is_some() {
+            self.save_reviews();
+        }
+        if self.dry_run {
+            info!("Dry run, {} changes not sent", plan.changes.len());
+        } else {
+            let mut m = self.todoist.manager();
+            engine::queue(&self.tree, &changes, &mut m);
+            engine::queue_completions(&completions, &mut m);
+            for &project_id in &archives {
+                m.archive_project(project_id);
+            }
+            let task = self.review.as_ref().map(|rules| rules.task.clone()).unwrap_or_default();
+            let review_tasks: Vec<(usize, TempId)> =
+                reviews.iter().map(|&project_id| (project_id, m.add_item(project_id, &task).0)).collect();
+            let mut result = CommandResponse::default();
+            let flushed = m.flush_into(&mut result);
+            // review tasks of the batches that went out count even when a later one failed
+            for (project_id, temp_id) in review_tasks {
+                if let Some(task_id) = result.id_of(&temp_id) {
+                    self.review_watch.task_added(project_id, task_id);
+                }
+            }
+            if !reviews.is_empty() {
+                self.save_reviews();
+            }
+            flushed?;
+            let failures = result.failures();
+            if !failures.is_empty() {
+                error!("{} commands failed this round", failures.len());
+            }
+        }
         info!("Step finished");
-        Ok(())
+        Ok(plan)
     }
 
+    /// Run a round every `sec` seconds. While rounds fail for a transient
+    /// reason the pause grows, up to `MAX_INTERVAL`; other errors, a panic
+    /// included, end the loop.
     pub fn loopit(&mut self, sec: u64) -> Result<()> {
+        let interval = Duration::new(sec, 0);
+        let mut backoff = Backoff::new(interval, Duration::new(MAX_INTERVAL, 0));
         loop {
             info!("Start a round of loop");
-            self.sync()?;
-            self.build_tree()?;
-            let mut m = self.todoist.manager();
-            for node in &self.tree.nodes {
-                traversal(node,
-                          &mut m,
-                          TraversalState::Unconstraint,
-                          self.nextaction_id.ok_or("nextaction_id is None".to_string())?,
-                          self.someday_id.ok_or("nextaction_id is None".to_string())?)
-            }
-            m.flush()?;
-            info!("Round finished, sleeping for {} sec", sec);
-            sleep(Duration::new(sec, 0));
+            let wait = self.attempt(&mut backoff)?.unwrap_or(interval);
+            info!("Round finished, sleeping for {:?}", wait);
+            sleep(wait);
         }
     }
-}
-
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum TraversalState {
-    Suppressed,
-    Unconstraint,
-    Active,
-}
 
-fn traversal(node: &Node,
-             manager: &mut CommandManager,
-             state: TraversalState,
-             naid: usize,
-             sdid: usize) {
-    use TraversalState::*;
-
-    let name: String = node.name();
-
-    let (is_parallel, is_sequential) = (name.ends_with(PARALLEL), name.ends_with(SEQUENTIAL));
-
-    match node.ntype {
-        NodeType::ItemNodeType(ref rnode) => {
-            if rnode.checked == 1 {
-                if rnode.labels.contains(&naid) || rnode.labels.contains(&sdid) {
-                    let v: Vec<usize> = rnode.labels
-                        .clone()
-                        .into_iter()
-                        .filter(|&u| u != naid && u != sdid)
-                        .collect();
-                    manager.set_item_label(rnode.id, v);
+    /// Listen for Todoist webhook deliveries on `addr` and run a round once a
+    /// burst of them is over for `debounce`. A round also runs when nothing
+    /// came in for `fallback`, to catch up with deliveries that got lost.
+    ///
+    /// A failed round doesn't end the loop, it is retried after a growing
+    /// pause: hyper can't stop a server, so the address stays taken until the
+    /// process ends and a fresh `serve` couldn't bind it again.
+    pub fn serve(&mut self, addr: &str, secret: &str, debounce: Duration, fallback: Duration) -> Result<()> {
+        let (tx, rx) = channel();
+        let mut listening = Server::http(addr)?.handle(webhook::WebhookHandler::new(secret, tx))?;
+        info!("Listening for webhooks on {}", listening.socket);
+
+        let mut backoff = Backoff::new(debounce, Duration::new(MAX_INTERVAL, 0));
+        // the first round catches up with whatever happened while we were away
+        let mut retry = Some(Duration::new(0, 0));
+        loop {
+            match retry {
+                Some(wait) => {
+                    sleep(wait);
+                    // the round about to run covers these
+                    rx.try_iter().count();
                 }
-            } else {
-                if state == Active &&
-                   (node.nodes.len() == 0 || node.nodes.iter().all(|l| l.checked()) ||
-                    (!is_parallel && !is_sequential)) &&
-                   !rnode.labels.contains(&sdid) {
-                    if !rnode.labels.contains(&naid) {
-                        let mut v = vec![naid];
-                        v.extend_from_slice(&rnode.labels);
-                        manager.set_item_label(rnode.id, v);
-                    }
-                } else {
-                    if rnode.labels.contains(&naid) {
-                        let v: Vec<usize> =
-                            rnode.labels.clone().into_iter().filter(|&u| u != naid).collect();
-                        manager.set_item_label(rnode.id, v);
+                None => {
+                    match webhook::wait(&rx, debounce, fallback) {
+                        Wakeup::Events(count) => info!("Start a round for {} webhook events", count),
+                        Wakeup::Fallback => info!("No webhook events for {:?}, start a round", fallback),
+                        Wakeup::Closed => {
+                            listening.close()?;
+                            return Err("the webhook server stopped".into());
+                        }
                     }
                 }
             }
+            retry = match self.attempt(&mut backoff) {
+                Ok(retry) => retry,
+                Err(err) => {
+                    let wait = backoff.fail(None);
+                    error!("Round failed: '{:?}', trying again in {:?}", err, wait);
+                    Some(wait)
+                }
+            };
         }
-        NodeType::ProjectNodeType(_) => {}
     }
 
-
-    let mut substate = match state {
-        Unconstraint => Active,
-        Suppressed => Suppressed,
-        Active => Active,
-    };
-
-    if is_parallel {
-        for node in &node.nodes {
-            traversal(node, manager, substate, naid, sdid);
-        }
-    } else if is_sequential {
-        for node in &node.nodes {
-            traversal(node, manager, substate, naid, sdid);
-            match node.ntype {
-                NodeType::ItemNodeType(ref node) => {
-                    if node.checked == 0 {
-                        substate = Suppressed;
-                    }
-                }
-                NodeType::ProjectNodeType(_) => {
-                    substate = Suppressed;
-                }
+    /// Run a round. When it fails for a transient reason, answer how long to
+    /// back off before the next one. A round that panics is an error like any
+    /// other, and the next one starts over with a full sync.
+    fn attempt(&mut self, backoff: &mut Backoff) -> Result<Option<Duration>> {
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| self.step())) {
+            Ok(result) => result,
+            Err(_) => {
+                self.reset();
+                Err("the round panicked".into())
             }
-        }
-    } else {
-        for node in &node.nodes {
-            traversal(node, manager, Unconstraint, naid, sdid);
+        };
+        match result {
+            Ok(_) => {
+                backoff.reset();
+                Ok(None)
+            }
+            Err(ref err) if err.is_transient() => {
+                let wait = backoff.fail(err.retry_after());
+                warn!("Round failed {} times in a row: '{}', backing off for {:?}",
+                      backoff.failures(),
+                      err,
+                      wait);
+                Ok(Some(wait))
+            }
+            Err(err) => Err(err),
         }
     }
 }
@@ -243,7 +542,10 @@ fn traversal(node: &Node,
 #[derive(Default, Debug)]
 struct BagOfThings {
     projects: BTreeSet<Project>,
+    sections: BTreeMap<usize, Section>,
     items: BTreeSet<Item>,
+    /// Only the notes giving a start date.
+    notes: BTreeMap<usize, Note>,
 }
 
 impl BagOfThings {
@@ -251,40 +553,207 @@ impl BagOfThings {
         for project in other.projects
             .as_ref()
             .unwrap() {
-            if project.is_archived == 1 {
-                self.projects.remove(project);
+            self.remove_project(project.id);
+            if project.is_archived == 1 || project.is_deleted == 1 {
+                // Todoist won't tell us about the items of a project that is gone
+                self.remove_items_of(project.id);
+                self.sections.retain(|_, section| section.project_id != project.id);
+                self.notes.retain(|_, note| note.project_id != project.id);
             } else {
-                self.projects.rebuild_insert(project.clone());
+                self.projects.insert(project.clone());
+            }
+        }
+
+        for section in other.sections.iter().flat_map(|s| s.iter()) {
+            self.sections.remove(&section.id);
+            if section.is_deleted == 0 && section.is_archived == 0 {
+                self.sections.insert(section.id, section.clone());
             }
         }
 
         for item in other.items
             .as_ref()
             .unwrap() {
-            if item.is_deleted == 1 || item.is_archived == 1 {
-                self.items.remove(item);
-            } else {
-                self.items.rebuild_insert(item.clone());
+            self.remove_item(item.id);
+            if item.is_deleted == 0 && item.is_archived == 0 {
+                self.items.insert(item.clone());
+            }
+        }
+
+        for note in other.notes.iter().flat_map(|n| n.iter()) {
+            self.notes.remove(&note.id);
+            if note.is_deleted == 0 && note.is_archived == 0 && defer::start_date(&note.content).is_some() {
+                self.notes.insert(note.id, note.clone());
+            }
+        }
+    }
+
+    /// Whether any item or note gives a start date.
+    fn has_start_dates(&self) -> bool {
+        !self.notes.is_empty() || self.items.iter().any(|item| defer::start_date(&item.content).is_some())
+    }
+
+    /// The start dates given in notes, the latest one where an item has several.
+    fn start_dates(&self) -> BTreeMap<usize, Day> {
+        let mut dates = BTreeMap::new();
+        for note in self.notes.values() {
+            if let Some(day) = defer::start_date(&note.content) {
+                let date = dates.entry(note.item_id).or_insert(day);
+                if day > *date {
+                    *date = day;
+                }
             }
         }
+        dates
+    }
+
+    // An update may come with a new item_order, so the stale copy can't be found
+    // through the set's ordering; look it up by id instead.
+    fn remove_project(&mut self, id: usize) {
+        if let Some(old) = self.projects.iter().find(|p| p.id == id).cloned() {
+            self.projects.remove(&old);
+        }
+    }
+
+    fn remove_item(&mut self, id: usize) {
+        if let Some(old) = self.items.iter().find(|i| i.id == id).cloned() {
+            self.items.remove(&old);
+        }
+    }
+
+    fn remove_items_of(&mut self, project_id: usize) {
+        let stale: Vec<Item> = self.items.iter().filter(|i| i.project_id == project_id).cloned().collect();
+        for item in stale {
+            self.items.remove(&item);
+        }
     }
 }
 
-fn push_level(to: &mut Vec<Node>, node: NodeType, level: usize) {
-    if level == 1 {
-        to.push(Node {
-            ntype: node,
-            nodes: vec![],
+/// What the tree needs to know to place a project or an item.
+trait Nested {
+    fn id(&self) -> usize;
+    fn indent(&self) -> usize;
+    fn parent_id(&self) -> Option<usize>;
+    fn node_type(&self) -> NodeType;
+}
+
+impl Nested for Project {
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn indent(&self) -> usize {
+        self.indent
+    }
+    fn parent_id(&self) -> Option<usize> {
+        self.parent_id
+    }
+    fn node_type(&self) -> NodeType {
+        NodeType::ProjectNodeType(self.clone())
+    }
+}
+
+impl Nested for Item {
+    fn id(&self) -> usize {
+        self.id
+    }
+    fn indent(&self) -> usize {
+        self.indent
+    }
+    fn parent_id(&self) -> Option<usize> {
+        self.parent_id
+    }
+    fn node_type(&self) -> NodeType {
+        NodeType::ItemNodeType(self.clone())
+    }
+}
+
+/// The parent of each of `nodes`, which are in display order: `parent_id` if
+/// given, otherwise the closest earlier node with a smaller indent. A missing
+/// parent, a loop or an indent without anything above it puts the node at the
+/// top; an indent gap puts it below the nearest shallower node.
+fn parents<T: Nested>(nodes: &[&T]) -> Vec<Option<usize>> {
+    let index: BTreeMap<usize, usize> = nodes.iter().enumerate().map(|(i, n)| (n.id(), i)).collect();
+    let mut parents = Vec::with_capacity(nodes.len());
+    let mut stack: Vec<(usize, usize)> = vec![];
+
+    for node in nodes {
+        let parent = match node.parent_id() {
+            Some(parent) if parent != node.id() && index.contains_key(&parent) => Some(parent),
+            Some(parent) => {
+                warn!("Parent {} of {} is missing, placing it at the top", parent, node.id());
+                None
+            }
+            None => {
+                while stack.last().map(|&(indent, _)| indent >= node.indent()).unwrap_or(false) {
+                    stack.pop();
+                }
+                match stack.last() {
+                    Some(&(indent, parent)) => {
+                        if node.indent() > indent + 1 {
+                            warn!("{} is indented {} levels below {}", node.id(), node.indent() - indent, parent);
+                        }
+                        Some(parent)
+                    }
+                    None => {
+                        if node.indent() > 1 {
+                            warn!("{} is indented without a parent, placing it at the top", node.id());
+                        }
+                        None
+                    }
+                }
+            }
+        };
+        stack.push((node.indent(), node.id()));
+        parents.push(parent);
+    }
+
+    // break loops among explicit parents, the node closing one goes to the top
+    for i in 0..nodes.len() {
+        let mut seen = BTreeSet::new();
+        let mut current = parents[i];
+        while let Some(parent) = current {
+            if parent == nodes[i].id() || !seen.insert(parent) {
+                warn!("{} is its own ancestor, placing it at the top", nodes[i].id());
+                parents[i] = None;
+                break;
+            }
+            current = parents[index[&parent]];
+        }
+    }
+    parents
+}
+
+/// Turn `nodes` below `parent` into tree nodes, keeping their order.
+fn grow<T: Nested>(nodes: &[&T],
+                   children: &BTreeMap<Option<usize>, Vec<usize>>,
+                   parent: Option<usize>)
+                   -> Vec<Node> {
+    children.get(&parent)
+        .map(|indices| {
+            indices.iter()
+                .map(|&i| {
+                    let mut node = Node::new(nodes[i].node_type());
+                    node.nodes = grow(nodes, children, Some(nodes[i].id()));
+                    node
+                })
+                .collect()
         })
-    } else {
-        let l = to.len() - 1;
-        push_level(&mut to[l].nodes, node, level - 1)
+        .unwrap_or_default()
+}
+
+/// Arrange `nodes` by parent, in display order.
+fn forest<T: Nested>(nodes: &[&T]) -> Vec<Node> {
+    let mut children: BTreeMap<Option<usize>, Vec<usize>> = BTreeMap::new();
+    for (i, parent) in parents(nodes).into_iter().enumerate() {
+        children.entry(parent).or_insert_with(Vec::new).push(i);
     }
+    grow(nodes, &children, None)
 }
 
 #[derive(Debug)]
 pub enum NodeType {
     ProjectNodeType(Project),
+    SectionNodeType(Section),
     ItemNodeType(Item),
 }
 
@@ -292,6 +761,7 @@ impl NodeType {
     fn id(&self) -> usize {
         match self {
             &NodeType::ProjectNodeType(ref project) => project.id,
+            &NodeType::SectionNodeType(ref section) => section.id,
             &NodeType::ItemNodeType(ref item) => item.id,
         }
     }
@@ -299,26 +769,30 @@ impl NodeType {
     fn name(&self) -> String {
         match self {
             &NodeType::ProjectNodeType(ref project) => project.name.clone(),
+            &NodeType::SectionNodeType(ref section) => section.name.clone(),
             &NodeType::ItemNodeType(ref item) => item.content.clone(),
         }
     }
 
     fn checked(&self) -> bool {
         match self {
-            &NodeType::ProjectNodeType(_) => false,
             &NodeType::ItemNodeType(ref node) => node.checked == 1,
+            _ => false,
         }
     }
 
     fn is_project(&self) -> bool {
         match self {
             &NodeType::ProjectNodeType(_) => true,
-            &NodeType::ItemNodeType(_) => false,
+            _ => false,
         }
     }
 
     fn is_item(&self) -> bool {
-        !self.is_project()
+        match self {
+            &NodeType::ItemNodeType(_) => true,
+            _ => false,
+        }
     }
 }
 
@@ -329,6 +803,13 @@ pub struct Node {
 }
 
 impl Node {
+    fn new(ntype: NodeType) -> Node {
+        Node {
+            ntype: ntype,
+            nodes: vec![],
+        }
+    }
+
     fn id(&self) -> usize {
         self.ntype.id()
     }
@@ -364,6 +845,28 @@ impl Node {
         }
     }
 
+    fn find_item(&self, id: usize) -> Option<&Item> {
+        match self.ntype {
+            NodeType::ItemNodeType(ref item) if item.id == id => Some(item),
+            _ => self.nodes.iter().filter_map(|node| node.find_item(id)).next(),
+        }
+    }
+
+    fn find_project(&self, id: usize) -> Option<&Project> {
+        match self.ntype {
+            NodeType::ProjectNodeType(ref project) if project.id == id => Some(project),
+            _ => self.nodes.iter().filter_map(|node| node.find_project(id)).next(),
+        }
+    }
+
+    fn parent_of(&self, id: usize) -> Option<usize> {
+        if self.nodes.iter().any(|node| node.is_item() && node.id() == id) {
+            Some(self.id())
+        } else {
+            self.nodes.iter().filter_map(|node| node.parent_of(id)).next()
+        }
+    }
+
     fn search_project(&mut self, id: usize) -> Option<&mut Self> {
         self.search(&|node: &Node| node.id() == id && node.is_project())
     }
@@ -383,6 +886,75 @@ impl TaskTree {
         TaskTree { nodes: Vec::new() }
     }
 
+    /// Arrange projects and items the way Todoist shows them. They are sorted
+    /// by `item_order` first, so any order will do. Parents come from
+    /// `parent_id` where Todoist gives one and from `indent` otherwise.
+    /// Inconsistent nesting is logged and fixed up, only an item of an
+    /// unknown project is an error.
+    pub fn build<'a, P, I>(projects: P, items: I) -> Result<TaskTree>
+        where P: IntoIterator<Item = &'a Project>,
+              I: IntoIterator<Item = &'a Item>
+    {
+        TaskTree::build_with_sections(projects, vec![], items)
+    }
+
+    /// Like `build`, with the items of a section nested in a node of its own.
+    /// Sections follow the items without one, in `section_order`.
+    pub fn build_with_sections<'a, P, S, I>(projects: P, sections: S, items: I) -> Result<TaskTree>
+        where P: IntoIterator<Item = &'a Project>,
+              S: IntoIterator<Item = &'a Section>,
+              I: IntoIterator<Item = &'a Item>
+    {
+        let projects: BTreeSet<&Project> = projects.into_iter().collect();
+        let items: BTreeSet<&Item> = items.into_iter().collect();
+        let projects: Vec<&Project> = projects.into_iter().collect();
+        let mut sections: Vec<&Section> = sections.into_iter().collect();
+        sections.sort_by_key(|s| (s.section_order, s.id));
+        let mut tree = TaskTree { nodes: forest(&projects) };
+
+        // items are nested within their project, below its sub-projects
+        let section_projects: BTreeMap<usize, usize> = sections.iter().map(|s| (s.id, s.project_id)).collect();
+        let mut by_scope: BTreeMap<(usize, Option<usize>), Vec<&Item>> = BTreeMap::new();
+        for item in items {
+            let section = match item.section_id {
+                Some(id) if section_projects.get(&id) != Some(&item.project_id) => {
+                    warn!("Section {} of item {} is unknown, placing it in the project", id, item.id);
+                    None
+                }
+                section => section,
+            };
+            by_scope.entry((item.project_id, section)).or_insert_with(Vec::new).push(item);
+        }
+        for (&(project_id, _), items) in by_scope.iter().filter(|&(&(_, section), _)| section.is_none()) {
+            let project = tree.search_project(project_id)
+                .ok_or_else(|| Error::from(ErrorKind::MissingProject(items[0].id, project_id)))?;
+            project.nodes.extend(forest(items));
+        }
+        for section in sections {
+            let items = by_scope.remove(&(section.project_id, Some(section.id))).unwrap_or_default();
+            match tree.search_project(section.project_id) {
+                Some(project) => {
+                    let mut node = Node::new(NodeType::SectionNodeType(section.clone()));
+                    node.nodes = forest(&items);
+                    project.nodes.push(node);
+                }
+                None if items.is_empty() => {
+                    warn!("Section {} belongs to unknown project {}", section.id, section.project_id)
+                }
+                None => return Err(ErrorKind::MissingProject(items[0].id, section.project_id).into()),
+            }
+        }
+        Ok(tree)
+    }
+
+    pub fn item(&self, id: usize) -> Option<&Item> {
+        self.nodes.iter().filter_map(|node| node.find_item(id)).next()
+    }
+
+    pub fn project(&self, id: usize) -> Option<&Project> {
+        self.nodes.iter().filter_map(|node| node.find_project(id)).next()
+    }
+
     fn search_project(&mut self, id: usize) -> Option<&mut Node> {
         for node in &mut self.nodes {
             if let Some(node) = node.search_project(id) {
@@ -391,4 +963,20 @@ impl TaskTree {
         }
         None
     }
+
+    /// The id of the project, section or item that item `id` is nested in.
+    pub fn parent_of(&self, id: usize) -> Option<usize> {
+        self.nodes.iter().filter_map(|node| node.parent_of(id)).next()
+    }
+
+    /// Work out the label changes this tree calls for, together with the labels they touch.
+    pub fn plan(&self, rules: &Rules) -> Plan {
+        Plan::new(self, &self.label_changes(rules), &self.completions(rules))
+    }
+
+    /// Queue the label changes and completions this tree calls for into `manager`.
+    pub fn queue(&self, manager: &mut CommandManager, rules: &Rules) {
+        engine::queue(self, &self.label_changes(rules), manager);
+        engine::queue_completions(&self.completions(rules), manager)
+    }
 }
\ No newline at end of file
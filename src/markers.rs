@@ -0,0 +1,137 @@
+//! How the name of a project, section or task says whether its children are
+//! worked on in parallel or one after the other.
+//!
+//! By default a name ending in `-` is parallel and one ending in `:` is
+//! sequential. Any string can be a marker instead, emoji included, at the end
+//! or start of a name or as a tag anywhere in it, like `[seq]`. Where a name
+//! carries no marker, `Rules` may still know the grouping from a label or the
+//! project's configured mode.
+
+use std::str::FromStr;
+
+use errors::*;
+use {PARALLEL, SEQUENTIAL};
+
+/// How the children of a node are worked on.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Parallel,
+    Sequential,
+}
+
+/// Parse `parallel` or `sequential`.
+impl FromStr for Grouping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Grouping> {
+        match s {
+            "parallel" => Ok(Grouping::Parallel),
+            "sequential" => Ok(Grouping::Sequential),
+            other => Err(format!("Unknown mode '{}', use parallel or sequential", other).into()),
+        }
+    }
+}
+
+/// One way of marking a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Marker {
+    /// The name ends with it, as `Errands-`.
+    Suffix(String),
+    /// The name starts with it, as `⏩ Errands`.
+    Prefix(String),
+    /// A word of the name is it, as `Errands [par]`.
+    Tag(String),
+}
+
+impl Marker {
+    /// Whether `name` carries the marker. `name` is expected to be trimmed and
+    /// without links, see `Markers::grouping`.
+    pub fn matches(&self, name: &str) -> bool {
+        match *self {
+            Marker::Suffix(ref s) => name.ends_with(s.as_str()),
+            Marker::Prefix(ref s) => name.starts_with(s.as_str()),
+            Marker::Tag(ref s) => name.split_whitespace().any(|word| word == s),
+        }
+    }
+}
+
+/// Parse `suffix:<marker>`, `prefix:<marker>` or `tag:<marker>`.
+impl FromStr for Marker {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Marker> {
+        let mut parts = spec.splitn(2, ':');
+        let (kind, marker) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        if marker.is_empty() {
+            return Err(format!("Marker '{}' is empty, use suffix:<marker>, prefix:<marker> or tag:<marker>",
+                               spec)
+                .into());
+        }
+        match kind {
+            "suffix" => Ok(Marker::Suffix(marker.into())),
+            "prefix" => Ok(Marker::Prefix(marker.into())),
+            "tag" => Ok(Marker::Tag(marker.into())),
+            other => Err(format!("Unknown kind of marker '{}', use suffix, prefix or tag", other).into()),
+        }
+    }
+}
+
+/// The markers in use. A name carrying both kinds counts as parallel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Markers {
+    pub parallel: Vec<Marker>,
+    pub sequential: Vec<Marker>,
+}
+
+impl Default for Markers {
+    fn default() -> Markers {
+        Markers {
+            parallel: vec![Marker::Suffix(PARALLEL.to_string())],
+            sequential: vec![Marker::Suffix(SEQUENTIAL.to_string())],
+        }
+    }
+}
+
+impl Markers {
+    pub fn grouping(&self, name: &str) -> Option<Grouping> {
+        let name = bare_name(name);
+        if self.parallel.iter().any(|m| m.matches(&name)) {
+            Some(Grouping::Parallel)
+        } else if self.sequential.iter().any(|m| m.matches(&name)) {
+            Some(Grouping::Sequential)
+        } else {
+            None
+        }
+    }
+}
+
+/// A name the way markers see it, links replaced by their text and without
+/// surrounding whitespace.
+pub(crate) fn bare_name(name: &str) -> String {
+    strip_links(name).trim().into()
+}
+
+/// Replace every Markdown link `[text](url)` by its text.
+fn strip_links(name: &str) -> String {
+    let mut out = String::new();
+    let mut rest = name;
+    while let Some(open) = rest.find('[') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open..];
+        let link = after.find("](")
+            .and_then(|close| after[close + 2..].find(')').map(|end| (close, close + 2 + end)));
+        match link {
+            // `[seq] see [docs](url)` has a tag before the link
+            Some((close, end)) if !after[1..close].contains(']') => {
+                out.push_str(&after[1..close]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                out.push('[');
+                rest = &after[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use engine::{LabelChange, Completion};
+use markers::Grouping;
+use TaskTree;
+
+/// Why an item's labels change.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The item is available and becomes a next action.
+    NextAction,
+    /// The item is done, its workflow labels go away.
+    Completed,
+    /// The item, a task above it or its project is on hold and stays off
+    /// the list, see `Rules::is_on_hold`.
+    Someday,
+    /// An earlier sibling in a sequential group is still open.
+    Blocked,
+    /// The item isn't in a parallel or sequential project or task.
+    Ungrouped,
+    /// The item's own subtasks carry the next actions.
+    HasSubtasks,
+    /// The item, or a task above it, has a start date still to come.
+    Deferred,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlannedChange {
+    pub item_id: usize,
+    pub content: String,
+    pub labels_before: Vec<usize>,
+    pub labels_after: Vec<usize>,
+    pub reason: Reason,
+}
+
+/// A task closed because all its subtasks are done.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlannedCompletion {
+    pub item_id: usize,
+    pub content: String,
+    pub grouping: Grouping,
+}
+
+/// A project archived because it is finished.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlannedArchive {
+    pub project_id: usize,
+    pub name: String,
+}
+
+/// A project that gets a review task.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlannedReview {
+    pub project_id: usize,
+    pub name: String,
+}
+
+/// The label changes, completions, archives and reviews of one round, before they are sent to Todoist.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Plan {
+    pub changes: Vec<PlannedChange>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub completions: Vec<PlannedCompletion>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub archives: Vec<PlannedArchive>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reviews: Vec<PlannedReview>,
+    /// Names of the labels the plan adds or removes, for display.
+    pub label_names: BTreeMap<usize, String>,
+}
+
+impl Plan {
+    /// Spell out `changes` and `completions` against the items they touch in `tree`.
+    pub fn new(tree: &TaskTree, changes: &[LabelChange], completions: &[Completion]) -> Plan {
+        let mut plan = Plan::default();
+        for change in changes {
+            if let Some(item) = tree.item(change.item_id) {
+                plan.changes.push(PlannedChange {
+                    item_id: item.id,
+                    content: item.content.clone(),
+                    labels_before: item.labels.clone(),
+                    labels_after: change.apply(&item.labels),
+                    reason: change.reason,
+                })
+            }
+        }
+        for completion in completions {
+            if let Some(item) = tree.item(completion.item_id) {
+                plan.completions.push(PlannedCompletion {
+                    item_id: item.id,
+                    content: item.content.clone(),
+                    grouping: completion.grouping,
+                })
+            }
+        }
+        plan
+    }
+
+    /// Spell out the projects to archive against `tree`.
+    pub fn add_archives(&mut self, tree: &TaskTree, project_ids: &[usize]) {
+        for &id in project_ids {
+            if let Some(project) = tree.project(id) {
+                self.archives.push(PlannedArchive {
+                    project_id: id,
+                    name: project.name.clone(),
+                })
+            }
+        }
+    }
+
+    /// Spell out the projects due for a review against `tree`.
+    pub fn add_reviews(&mut self, tree: &TaskTree, project_ids: &[usize]) {
+        for &id in project_ids {
+            if let Some(project) = tree.project(id) {
+                self.reviews.push(PlannedReview {
+                    project_id: id,
+                    name: project.name.clone(),
+                })
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.completions.is_empty() && self.archives.is_empty() &&
+        self.reviews.is_empty()
+    }
+
+    fn render_labels(&self, labels: &[usize]) -> String {
+        let names: Vec<String> = labels.iter()
+            .map(|id| self.label_names.get(id).cloned().unwrap_or_else(|| format!("{}", id)))
+            .collect();
+        format!("[{}]", names.join(", "))
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Nothing to change");
+        }
+        for completion in &self.completions {
+            writeln!(f,
+                     "Close {} '{}', the subtasks of this {} task are done",
+                     completion.item_id,
+                     completion.content,
+                     match completion.grouping {
+                         Grouping::Parallel => "parallel",
+                         Grouping::Sequential => "sequential",
+                     })?;
+        }
+        for archive in &self.archives {
+            writeln!(f,
+                     "Archive project {} '{}', all its tasks are done",
+                     archive.project_id,
+                     archive.name)?;
+        }
+        for review in &self.reviews {
+            writeln!(f, "Add a review task to project {} '{}'", review.project_id, review.name)?;
+        }
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+        writeln!(f,
+                 "{:<12} {:<40} {:<24} {:<24} {}",
+                 "ITEM",
+                 "CONTENT",
+                 "BEFORE",
+                 "AFTER",
+                 "REASON")?;
+        for change in &self.changes {
+            let mut content: String = change.content.chars().take(40).collect();
+            if content.len() < change.content.len() {
+                content.pop();
+                content.push('…');
+            }
+            writeln!(f,
+                     "{:<12} {:<40} {:<24} {:<24} {:?}",
+                     change.item_id,
+                     content,
+                     self.render_labels(&change.labels_before),
+                     self.render_labels(&change.labels_after),
+                     change.reason)?;
+        }
+        Ok(())
+    }
+}
@@ -1,65 +1,135 @@
 use std::collections::BTreeMap;
 use std::cmp::{PartialOrd, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
 
-use hyper::Client;
-use hyper::client::Response;
-use hyper::header::{ContentType, UserAgent, Accept, qitem};
-use hyper::status::StatusCode;
-use hyper::net::HttpsConnector;
-use hyper_rustls::TlsClient;
-
-use serde_json::{from_reader, from_str, to_string, Value as JsonValue};
+use serde_json::{from_str, to_string, Value as JsonValue};
 
+use backoff::Backoff;
 use errors::*;
-
-use uuid::Uuid;
-
-use url::form_urlencoded;
-
-use std::time::Duration;
+use command::{Command, CommandKind, CommandId, TempId, ObjectId, CommandStatus, CommandError};
+use transport::{Transport, HyperTransport, HttpRequest, HttpResponse};
+use v9::{self, IdMap};
 
 #[allow(dead_code)]
 pub const VERSION: &'static str = "v7";
 const ENDPOINT: &'static str = "https://todoist.com/API/v7/sync";
 
+/// The version of the sync protocol a `Todoist` speaks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V7,
+    V9,
+}
+
+impl Default for ApiVersion {
+    fn default() -> ApiVersion {
+        ApiVersion::V7
+    }
+}
+/// Todoist refuses requests with more than 100 commands.
+pub const BATCH_SIZE: usize = 100;
+/// The longest a flush waits between two attempts, unless Todoist asks for more.
+const MAX_RETRY_DELAY: u64 = 60;
+
 pub struct Todoist {
     token: String,
     sync_token: String,
-    client: Client,
+    transport: Box<Transport>,
+    retry_attempts: usize,
+    retry_delay: Duration,
+    batch_size: usize,
+    version: ApiVersion,
+    ids: IdMap,
 }
 
 impl Todoist {
     pub fn new(token: &str) -> Todoist {
-        let mut client = Client::with_connector(HttpsConnector::new(TlsClient::new()));
-        client.set_read_timeout(Some(Duration::from_secs(30)));
-        client.set_write_timeout(Some(Duration::from_secs(30)));
+        Todoist::with_transport(token, HyperTransport::new())
+    }
+
+    pub fn with_transport<T>(token: &str, transport: T) -> Todoist
+        where T: Transport + 'static
+    {
         Todoist {
             token: token.into(),
             sync_token: "*".into(),
-            client: client,
+            transport: Box::new(transport),
+            retry_attempts: 3,
+            retry_delay: Duration::from_secs(1),
+            batch_size: BATCH_SIZE,
+            version: ApiVersion::V7,
+            ids: IdMap::default(),
         }
     }
 
+    /// How often a flush sends a command that failed for a transient reason,
+    /// and how long it waits at least before the first retry; later retries
+    /// back off. Managers created afterwards pick this up.
+    pub fn set_retry(&mut self, attempts: usize, delay: Duration) {
+        self.retry_attempts = attempts;
+        self.retry_delay = delay;
+    }
+
+    /// How many commands a flush sends per request. Managers created
+    /// afterwards pick this up.
+    pub fn set_batch_size(&mut self, size: usize) {
+        self.batch_size = size;
+    }
+
+    pub fn set_api_version(&mut self, version: ApiVersion) {
+        self.version = version;
+    }
+
+    pub fn api_version(&self) -> ApiVersion {
+        self.version
+    }
+
+    /// The numbers handed out for v9 string ids so far.
+    pub fn ids(&self) -> &IdMap {
+        &self.ids
+    }
+
+    /// For ids kept apart from the sync state, which need numbers of their own.
+    pub fn ids_mut(&mut self) -> &mut IdMap {
+        &mut self.ids
+    }
+
+    /// Take back the numbers of an earlier run, the cached items use them.
+    pub fn restore_ids(&mut self, ids: IdMap) {
+        self.ids = ids;
+    }
+
     pub fn set_sync_token(&mut self, token: &str) {
         self.sync_token = token.into();
     }
 
-    fn post<'a, I>(&self, data: I) -> Result<Response>
+    pub fn sync_token(&self) -> &str {
+        &self.sync_token
+    }
+
+    fn post<'a, I>(&self, data: I) -> Result<HttpResponse>
         where I: IntoIterator<Item = (&'a str, String)>
     {
-        let mut initial = vec![("token", self.token.clone())];
-
-        initial.extend(data);
-        let dt = form_urlencoded::Serializer::new(String::new()).extend_pairs(initial).finish();
-
-        let resp: Response = self.client
-            .post(ENDPOINT)
-            .header(ContentType(mime!(Application/WwwFormUrlEncoded; Charset=Utf8)))
-            .header(UserAgent("curl/7.43.0".into()))
-            .header(Accept(vec![qitem(mime!(_/_))]))
-            .body(&dt)
-            .send()?;
-        Ok(resp)
+        let mut form = vec![];
+        let mut headers = vec![];
+        let url = match self.version {
+            ApiVersion::V7 => {
+                form.push(("token".to_string(), self.token.clone()));
+                ENDPOINT
+            }
+            ApiVersion::V9 => {
+                headers.push(("Authorization".to_string(), format!("Bearer {}", self.token)));
+                v9::ENDPOINT
+            }
+        };
+        form.extend(data.into_iter().map(|(k, v)| (k.to_string(), v)));
+
+        self.transport.post(&HttpRequest {
+            url: url.into(),
+            headers: headers,
+            form: form,
+        })
     }
 
     pub fn sync(&mut self) -> Result<TodoistResponse> {
@@ -70,12 +140,40 @@ impl Todoist {
         let resp = self.post(vec![("sync_token", self.sync_token.clone()),
                        ("resource_types", format!(r#"["{}"]"#, fields.join(r#"",""#)))])?;
 
-        if resp.status != StatusCode::Ok {
-            Err(format!("status code is: {}", resp.status).into())
-        } else {
-            let result: TodoistResponse = from_reader(resp)?;
-            self.sync_token = result.sync_token.clone();
-            Ok(result)
+        check_status(&resp)?;
+        let result: TodoistResponse = match self.version {
+            ApiVersion::V7 => from_str(&resp.body)?,
+            ApiVersion::V9 => v9::response(&mut self.ids, from_str(&resp.body)?),
+        };
+        self.sync_token = result.sync_token.clone();
+        Ok(result)
+    }
+
+    /// The user as of now, leaving the sync token and the v9 ids alone. An
+    /// incremental sync only carries the user when it changed, and the offset
+    /// of its time zone moving with daylight saving time isn't such a change.
+    pub fn user(&mut self) -> Result<Option<User>> {
+        let resp = self.post(vec![("sync_token", "*".to_string()),
+                       ("resource_types", r#"["user"]"#.to_string())])?;
+
+        check_status(&resp)?;
+        Ok(match self.version {
+            ApiVersion::V7 => from_str::<TodoistResponse>(&resp.body)?.user,
+            ApiVersion::V9 => v9::user(&mut self.ids, from_str(&resp.body)?),
+        })
+    }
+
+    fn encode_commands(&self, commands: &[Command]) -> Result<String> {
+        match self.version {
+            ApiVersion::V7 => Ok(to_string(commands)?),
+            ApiVersion::V9 => Ok(v9::commands(&self.ids, commands)?.to_string()),
+        }
+    }
+
+    fn decode_commands(&mut self, sent: &[Command], body: &str) -> Result<CommandResponse> {
+        match self.version {
+            ApiVersion::V7 => Ok(from_str(body)?),
+            ApiVersion::V9 => Ok(v9::command_response(&mut self.ids, sent, from_str(body)?)),
         }
     }
 
@@ -87,116 +185,375 @@ impl Todoist {
         let mut m = self.manager();
         let (temp_id, uuid) = m.add_label(name);
         let result = m.flush()?;
-        if result.sync_status[&uuid] != "ok" {
-            Err(format!("Add label '{}' fail", name).into())
-        } else {
-            let id = result.temp_id_mapping[&temp_id];
-
-            Ok(Label {
-                id: id,
-                name: name.into(),
-            })
+        match (result.status(&uuid), result.id_of(&temp_id)) {
+            (Some(&CommandStatus::Ok), Some(id)) => {
+                Ok(Label {
+                    id: id,
+                    name: name.into(),
+                    ..Default::default()
+                })
+            }
+            (Some(&CommandStatus::Failed(ref err)), _) => {
+                Err(format!("Add label '{}' fail: {}", name, err).into())
+            }
+            _ => Err(format!("Add label '{}' fail", name).into()),
         }
     }
 }
 
 pub struct CommandManager<'a> {
     todoist: &'a mut Todoist,
-    commands: Vec<JsonValue>,
+    commands: Vec<Command>,
+    retry_attempts: usize,
+    retry_delay: Duration,
+    batch_size: usize,
 }
 
 impl<'a> CommandManager<'a> {
     pub fn new(td: &'a mut Todoist) -> CommandManager {
         CommandManager {
+            retry_attempts: td.retry_attempts,
+            retry_delay: td.retry_delay,
+            batch_size: td.batch_size,
             todoist: td,
             commands: vec![],
         }
     }
 
-    pub fn add_label(&mut self, name: &str) -> (Uuid, Uuid) {
-        let temp_id = Uuid::new_v4();
-        let uuid = Uuid::new_v4();
-        self.commands.push(json!({
-                "type": "label_add",
-                "args": json!({
-                    "name": name
-                }),
-                "temp_id": format!("{}", temp_id),
-                "uuid": format!("{}", uuid)
-            }
-        ));
-        (temp_id, uuid)
+    pub fn set_retry(&mut self, attempts: usize, delay: Duration) {
+        self.retry_attempts = attempts;
+        self.retry_delay = delay;
     }
 
-    pub fn set_item_label(&mut self, id: usize, label_ids: Vec<usize>) -> Uuid {
-        let uuid = Uuid::new_v4();
-        self.commands.push(json! ({
-            "type": "item_update",
-            "uuid": format!("{}", uuid),
-            "args": json! ({
-                "id": id,
-                "labels": label_ids
-            })
-        }));
-        uuid
+    pub fn set_batch_size(&mut self, size: usize) {
+        self.batch_size = size;
     }
 
-    pub fn complete_item(&mut self, id: usize) -> Uuid {
-        let uuid = Uuid::new_v4();
-        self.commands.push(json!({
-            "type": "close_item",
-            "uuid": format!("{}", uuid),
-            "args": json! ({
-                "id": id
-            })
-        }));
+    /// Queue any command, returns its uuid.
+    pub fn push(&mut self, kind: CommandKind) -> CommandId {
+        let command = Command::new(kind);
+        let uuid = command.uuid;
+        self.commands.push(command);
         uuid
     }
 
-    pub fn archive_project(&mut self, id: usize) -> Uuid {
-        let uuid = Uuid::new_v4();
-        self.commands.push(json!({
-            "type" : "project_archive",
-            "uuid" : format!("{}", uuid),
-            "args" : json!({
-                "ids" : json!([id])
-            })
-        }));
-        uuid
+    /// Queue a command that creates an object, returns its temp id and uuid.
+    pub fn push_create(&mut self, kind: CommandKind) -> (TempId, CommandId) {
+        let command = Command::create(kind);
+        let (temp_id, uuid) = (command.temp_id.unwrap(), command.uuid);
+        self.commands.push(command);
+        (temp_id, uuid)
+    }
+
+    pub fn add_label(&mut self, name: &str) -> (TempId, CommandId) {
+        self.push_create(CommandKind::LabelAdd { name: name.into() })
+    }
+
+    pub fn set_item_label(&mut self, id: usize, label_ids: Vec<usize>) -> CommandId {
+        self.push(CommandKind::ItemUpdate {
+            id: id.into(),
+            labels: Some(label_ids.into_iter().map(ObjectId::from).collect()),
+        })
+    }
+
+    pub fn complete_item(&mut self, id: usize) -> CommandId {
+        self.push(CommandKind::CloseItem { id: id.into() })
+    }
+
+    pub fn archive_project(&mut self, id: usize) -> CommandId {
+        self.push(CommandKind::ProjectArchive { ids: vec![id.into()] })
+    }
+
+    /// Add an item at the top level of a project, which may be one added
+    /// earlier in this queue. Queue `CommandKind::ItemAdd` for more options.
+    pub fn add_item<P: Into<ObjectId>>(&mut self, project_id: P, content: &str) -> (TempId, CommandId) {
+        self.push_create(CommandKind::ItemAdd {
+            content: content.into(),
+            project_id: project_id.into(),
+            parent_id: None,
+            indent: None,
+            item_order: None,
+            labels: None,
+            priority: None,
+            date_string: None,
+        })
+    }
+
+    pub fn move_item<I, P>(&mut self, id: I, from_project: P, to_project: P) -> CommandId
+        where I: Into<ObjectId>,
+              P: Into<ObjectId>
+    {
+        let mut project_items = BTreeMap::new();
+        project_items.insert(from_project.into(), vec![id.into()]);
+        self.push(CommandKind::ItemMove {
+            project_items: project_items,
+            to_project: to_project.into(),
+        })
+    }
+
+    pub fn delete_item<I: Into<ObjectId>>(&mut self, id: I) -> CommandId {
+        self.push(CommandKind::ItemDelete { ids: vec![id.into()] })
+    }
+
+    pub fn uncomplete_item<I: Into<ObjectId>>(&mut self, id: I) -> CommandId {
+        self.push(CommandKind::ItemUncomplete { ids: vec![id.into()] })
+    }
+
+    /// Give items a new place, as `(id, item_order, indent)`.
+    pub fn reorder_items(&mut self, orders: &[(usize, usize, usize)]) -> CommandId {
+        self.push(CommandKind::ItemUpdateOrdersIndents {
+            ids_to_orders_indents: orders.iter().map(|&(id, order, indent)| (id.into(), (order, indent))).collect(),
+        })
     }
 
+    /// Add a top level project. Queue `CommandKind::ProjectAdd` to nest it.
+    pub fn add_project(&mut self, name: &str) -> (TempId, CommandId) {
+        self.push_create(CommandKind::ProjectAdd {
+            name: name.into(),
+            parent_id: None,
+            indent: None,
+            item_order: None,
+        })
+    }
+
+    pub fn rename_project<I: Into<ObjectId>>(&mut self, id: I, name: &str) -> CommandId {
+        self.push(CommandKind::ProjectUpdate {
+            id: id.into(),
+            name: Some(name.into()),
+            collapsed: None,
+        })
+    }
+
+    /// Give projects a new place, as `(id, item_order, indent)`.
+    pub fn reorder_projects(&mut self, orders: &[(usize, usize, usize)]) -> CommandId {
+        self.push(CommandKind::ProjectUpdateOrdersIndents {
+            ids_to_orders_indents: orders.iter().map(|&(id, order, indent)| (id.into(), (order, indent))).collect(),
+        })
+    }
+
+    pub fn add_note<I: Into<ObjectId>>(&mut self, item_id: I, content: &str) -> (TempId, CommandId) {
+        self.push_create(CommandKind::NoteAdd {
+            item_id: item_id.into(),
+            content: content.into(),
+        })
+    }
+
+    /// The commands queued so far, in the order they will be sent.
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// Fold several `item_update`s of the same item into the last one. The
+    /// uuids of the folded commands won't show up in the response.
+    pub fn coalesce(&mut self) {
+        let mut merged: Vec<Command> = Vec::with_capacity(self.commands.len());
+        for mut command in self.commands.drain(..) {
+            if let Some(pos) = merged.iter().position(|m| m.kind.merges_with(&command.kind)) {
+                let mut earlier = merged.remove(pos);
+                earlier.kind.merge(&command.kind);
+                command.kind = earlier.kind;
+            }
+            merged.push(command);
+        }
+        self.commands = merged;
+    }
+
+    /// Send everything queued, `batch_size` commands per request. Commands
+    /// that fail for a transient reason, or that Todoist doesn't report on, go
+    /// out again under the same uuid, so Todoist carries each of them out at
+    /// most once. Temp ids created by earlier requests are replaced by their
+    /// real ids before a command goes out. Permanent failures are logged and
+    /// left in the returned response.
     pub fn flush(self) -> Result<CommandResponse> {
-        use std::io::Read;
+        let mut result = CommandResponse::default();
+        self.flush_into(&mut result)?;
+        Ok(result)
+    }
 
+    /// Like `flush`, but the answers go into `result` as the batches come
+    /// back. When a batch fails for good the error is returned and `result`
+    /// still holds what the batches before it did, temp ids included.
+    pub fn flush_into(self, result: &mut CommandResponse) -> Result<()> {
         let count = self.commands.len();
         info!("{} items flushed", count);
-        if count == 0 {
-            return Ok(CommandResponse::default());
+        let batch_size = ::std::cmp::max(self.batch_size, 1);
+        let batches = (count + batch_size - 1) / batch_size;
+        let mut commands = self.commands;
+        let mut batch = 0;
+
+        while !commands.is_empty() {
+            let rest = if commands.len() > batch_size {
+                commands.split_off(batch_size)
+            } else {
+                vec![]
+            };
+            batch += 1;
+            if batches > 1 {
+                info!("Sending batch {} of {}", batch, batches);
+            }
+            send_batch(self.todoist,
+                       commands,
+                       self.retry_attempts,
+                       self.retry_delay,
+                       result)?;
+            commands = rest;
         }
-        let mut resp = self.todoist
-            .post(vec![("commands", to_string(&self.commands)?)])?;
-        let mut s = String::new();
-        let _ = resp.read_to_string(&mut s);
-        debug!("flush response is '{}'", s);
-        let result = from_str(&s)?;
-        Ok(result)
+        Ok(())
     }
 }
 
+/// Turn a non-200 answer into an error, throttling and server trouble get
+/// their own kinds so that callers can back off.
+fn check_status(resp: &HttpResponse) -> Result<()> {
+    let retry_after = || resp.header("Retry-After").and_then(|v| v.trim().parse::<u64>().ok());
+    match resp.status {
+        200 => Ok(()),
+        429 => Err(ErrorKind::RateLimited(retry_after()).into()),
+        status if status >= 500 => Err(ErrorKind::ServerError(status, retry_after()).into()),
+        status => Err(format!("status code is: {}", status).into()),
+    }
+}
+
+/// Send one batch until every command has an answer or the attempts run out.
+/// Between attempts it backs off, starting from `delay`.
+fn send_batch(todoist: &mut Todoist,
+              mut pending: Vec<Command>,
+              attempts: usize,
+              delay: Duration,
+              result: &mut CommandResponse)
+              -> Result<()> {
+    let mut backoff = Backoff::new(delay, Duration::from_secs(MAX_RETRY_DELAY));
+    let mut retry_after = None;
+    let mut attempt = 0;
+
+    while !pending.is_empty() {
+        attempt += 1;
+        if attempt > 1 {
+            let wait = backoff.fail(retry_after.take());
+            info!("Retrying {} commands in {:?}, attempt {}", pending.len(), wait, attempt);
+            sleep(wait);
+        }
+        let last = attempt >= attempts;
+
+        for command in &mut pending {
+            command.kind.resolve(&result.temp_id_mapping);
+            debug!("flushing {}", command);
+        }
+        let resp = todoist.post(vec![("commands", todoist.encode_commands(&pending)?)])
+            .and_then(|resp| check_status(&resp).map(|_| resp));
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(ref err) if err.is_transient() && !last => {
+                warn!("Issue '{}' while flushing", err);
+                retry_after = err.retry_after();
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        debug!("flush response is '{}'", resp.body);
+        let resp = todoist.decode_commands(&pending, &resp.body)?;
+
+        let mut retry = vec![];
+        for command in pending {
+            match resp.status(&command.uuid) {
+                Some(&CommandStatus::Ok) => {}
+                Some(&CommandStatus::Failed(ref err)) if err.is_transient() && !last => {
+                    warn!("Command {} failed, will retry: {}", command, err);
+                    retry.push(command);
+                }
+                Some(&CommandStatus::Failed(ref err)) => {
+                    error!("Command {} failed: {}", command, err);
+                }
+                None if !last => retry.push(command),
+                None => error!("Command {} got no answer", command),
+            }
+        }
+        result.absorb(resp);
+        pending = retry;
+    }
+    Ok(())
+}
+
 #[derive(Debug, Deserialize, Default)]
 pub struct CommandResponse {
-    sync_status: BTreeMap<Uuid, String>,
-    temp_id_mapping: BTreeMap<Uuid, usize>,
+    #[serde(default)]
+    pub(crate) sync_status: BTreeMap<CommandId, CommandStatus>,
+    #[serde(default)]
+    pub(crate) temp_id_mapping: BTreeMap<TempId, usize>,
+}
+
+impl CommandResponse {
+    pub fn status(&self, uuid: &CommandId) -> Option<&CommandStatus> {
+        self.sync_status.get(uuid)
+    }
+
+    pub fn is_ok(&self, uuid: &CommandId) -> bool {
+        self.status(uuid).map(CommandStatus::is_ok).unwrap_or(false)
+    }
+
+    /// The real id Todoist gave the object created under `temp_id`.
+    pub fn id_of(&self, temp_id: &TempId) -> Option<usize> {
+        self.temp_id_mapping.get(temp_id).cloned()
+    }
+
+    pub fn statuses(&self) -> &BTreeMap<CommandId, CommandStatus> {
+        &self.sync_status
+    }
+
+    pub fn temp_id_mapping(&self) -> &BTreeMap<TempId, usize> {
+        &self.temp_id_mapping
+    }
+
+    /// The commands that failed for good.
+    pub fn failures(&self) -> Vec<(CommandId, &CommandError)> {
+        self.sync_status
+            .iter()
+            .filter_map(|(uuid, status)| match *status {
+                CommandStatus::Failed(ref err) => Some((*uuid, err)),
+                CommandStatus::Ok => None,
+            })
+            .collect()
+    }
+
+    /// Take in the response of a later request, its statuses replace ours.
+    fn absorb(&mut self, other: CommandResponse) {
+        self.sync_status.extend(other.sync_status);
+        self.temp_id_mapping.extend(other.temp_id_mapping);
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// Fields Todoist sends that the structs here don't name, kept so that
+/// nothing is lost when an object is stored or passed on.
+pub type Extra = BTreeMap<String, JsonValue>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct TodoistResponse {
     pub projects: Option<Vec<Project>>,
+    #[serde(default)]
+    pub sections: Option<Vec<Section>>,
     pub notes: Option<Vec<Note>>,
     pub items: Option<Vec<Item>>,
     pub labels: Option<Vec<Label>>,
     pub user: Option<User>,
-    full_sync: bool,
+    #[serde(default)]
+    pub filters: Option<Vec<Filter>>,
+    #[serde(default)]
+    pub reminders: Option<Vec<Reminder>>,
+    #[serde(default)]
+    pub collaborators: Option<Vec<Collaborator>>,
+    #[serde(default)]
+    pub collaborator_states: Option<Vec<CollaboratorState>>,
+    #[serde(default)]
+    pub project_notes: Option<Vec<ProjectNote>>,
+    #[serde(default)]
+    pub locations: Option<Vec<Location>>,
+    #[serde(default)]
+    pub live_notifications: Option<Vec<LiveNotification>>,
+    #[serde(default)]
+    pub live_notifications_last_read_id: Option<i64>,
+    #[serde(default)]
+    pub day_orders_timestamp: Option<String>,
+    pub(crate) full_sync: bool,
     pub sync_token: String,
 }
 
@@ -210,20 +567,34 @@ impl TodoistResponse {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Label {
     pub name: String,
     pub id: usize,
+    #[serde(default)]
+    pub color: usize,
+    #[serde(default)]
+    pub item_order: usize,
+    #[serde(default)]
+    pub is_deleted: usize,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Item {
     pub indent: usize,
     pub item_order: usize,
     pub id: usize,
+    /// The parent item, v9 gives this instead of `indent`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<usize>,
     pub date_added: String,
     pub priority: usize,
     pub project_id: usize,
+    /// The section the item sits in, sections came after v7.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section_id: Option<usize>,
     pub content: String,
     pub all_day: bool,
     pub labels: Vec<usize>,
@@ -231,9 +602,76 @@ pub struct Item {
     pub is_archived: usize,
     pub checked: usize,
     pub in_history: usize,
+    /// The due date, as in `"Sun 31 Jul 2016 23:59:59 +0000"`.
+    #[serde(default)]
+    pub due_date_utc: Option<String>,
+    /// The due date the way the user typed it, as in `"every monday"`.
+    #[serde(default)]
+    pub date_string: Option<String>,
+    #[serde(default)]
+    pub date_lang: Option<String>,
+    /// Whether the due date repeats, as Todoist works it out. Only v9 says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_recurring: Option<bool>,
+    /// The collaborator the item is assigned to.
+    #[serde(default)]
+    pub responsible_uid: Option<i64>,
+    #[serde(default)]
+    pub assigned_by_uid: Option<i64>,
+    #[serde(default)]
+    pub user_id: Option<i64>,
+    #[serde(default)]
+    pub sync_id: Option<i64>,
+    #[serde(default)]
+    pub collapsed: usize,
+    /// The position in the today and next 7 days views, -1 when not set.
+    #[serde(default)]
+    pub day_order: i64,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Item {
+    /// Whether the due date repeats. Closing such an item only moves it to the
+    /// next date. v7 doesn't say, so there it goes by the English words of
+    /// the date, as in `"every monday"` or `"ev 2 weeks"`.
+    pub fn is_recurring(&self) -> bool {
+        if let Some(recurring) = self.is_recurring {
+            return recurring;
+        }
+        self.date_string
+            .as_ref()
+            .map(|date| {
+                date.to_lowercase().split_whitespace().any(|word| {
+                    match word {
+                        "every" | "every!" | "ev" | "ev!" | "daily" | "weekly" | "monthly" | "yearly" => true,
+                        _ => false,
+                    }
+                })
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// A file, image or web page attached to a note.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct FileAttachment {
+    pub resource_type: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    #[serde(default)]
+    pub file_type: Option<String>,
+    #[serde(default)]
+    pub file_size: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Note {
     pub is_deleted: usize,
     pub is_archived: usize,
@@ -242,6 +680,35 @@ pub struct Note {
     pub project_id: usize,
     pub id: usize,
     pub posted: String,
+    #[serde(default)]
+    pub posted_uid: Option<i64>,
+    #[serde(default)]
+    pub uids_to_notify: Option<Vec<i64>>,
+    #[serde(default)]
+    pub file_attachment: Option<FileAttachment>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A note on a project rather than an item.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ProjectNote {
+    pub id: usize,
+    pub project_id: usize,
+    pub content: String,
+    pub posted: String,
+    #[serde(default)]
+    pub posted_uid: Option<i64>,
+    #[serde(default)]
+    pub uids_to_notify: Option<Vec<i64>>,
+    #[serde(default)]
+    pub file_attachment: Option<FileAttachment>,
+    #[serde(default)]
+    pub is_deleted: usize,
+    #[serde(default)]
+    pub is_archived: usize,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -250,7 +717,166 @@ pub struct Project {
     pub id: usize,
     pub item_order: usize,
     pub indent: usize,
+    /// The parent project, v9 gives this instead of `indent`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<usize>,
+    pub is_archived: usize,
+    #[serde(default)]
+    pub is_deleted: usize,
+    #[serde(default)]
+    pub color: usize,
+    #[serde(default)]
+    pub collapsed: usize,
+    #[serde(default)]
+    pub shared: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A named part of a project, sent by v9 only.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Section {
+    pub id: usize,
+    pub name: String,
+    pub project_id: usize,
+    pub section_order: usize,
+    #[serde(default)]
     pub is_archived: usize,
+    #[serde(default)]
+    pub is_deleted: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Filter {
+    pub id: usize,
+    pub name: String,
+    /// The filter expression, as in `"p:Reading & @next_action"`.
+    pub query: String,
+    #[serde(default)]
+    pub color: usize,
+    #[serde(default)]
+    pub item_order: usize,
+    #[serde(default)]
+    pub is_deleted: usize,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Reminder {
+    pub id: usize,
+    pub item_id: usize,
+    /// `"relative"`, `"absolute"` or `"location"`.
+    #[serde(rename = "type")]
+    pub reminder_type: String,
+    #[serde(default)]
+    pub notify_uid: Option<i64>,
+    /// `"email"`, `"sms"` or `"push"`.
+    #[serde(default)]
+    pub service: Option<String>,
+    #[serde(default)]
+    pub due_date_utc: Option<String>,
+    #[serde(default)]
+    pub date_string: Option<String>,
+    #[serde(default)]
+    pub date_lang: Option<String>,
+    /// Minutes before the item is due, for relative reminders.
+    #[serde(default)]
+    pub minute_offset: Option<i64>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub loc_lat: Option<String>,
+    #[serde(default)]
+    pub loc_long: Option<String>,
+    /// `"on_enter"` or `"on_leave"`, for location reminders.
+    #[serde(default)]
+    pub loc_trigger: Option<String>,
+    #[serde(default)]
+    pub radius: Option<u64>,
+    #[serde(default)]
+    pub is_deleted: usize,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Collaborator {
+    pub id: i64,
+    pub email: String,
+    pub full_name: String,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub image_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Whether a collaborator is in a shared project.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CollaboratorState {
+    pub project_id: usize,
+    pub user_id: i64,
+    /// `"active"` or `"invited"`.
+    pub state: String,
+    #[serde(default)]
+    pub is_deleted: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A place used by location reminders, sent as `[name, lat, long]`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Location(pub String, pub String, pub String);
+
+impl Location {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn lat(&self) -> &str {
+        &self.1
+    }
+
+    pub fn long(&self) -> &str {
+        &self.2
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct LiveNotification {
+    pub id: usize,
+    /// As in `"karma_level"` or `"item_assigned"`, the other fields depend on it.
+    pub notification_type: String,
+    #[serde(default)]
+    pub notification_key: Option<String>,
+    #[serde(default)]
+    pub seq_no: Option<i64>,
+    /// Unix time.
+    #[serde(default)]
+    pub created: i64,
+    #[serde(default)]
+    pub from_uid: Option<i64>,
+    #[serde(default)]
+    pub project_id: Option<usize>,
+    #[serde(default)]
+    pub item_id: Option<usize>,
+    #[serde(default)]
+    pub is_deleted: usize,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// The user's time zone, as in `{"hours": 8, "minutes": 0, "gmt_string": "+08:00"}`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TzInfo {
+    pub timezone: String,
+    pub gmt_string: String,
+    pub hours: i64,
+    pub minutes: i64,
+    #[serde(default)]
+    pub is_dst: usize,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -261,6 +887,37 @@ pub struct User {
     pub full_name: String,
     pub inbox_project: i64,
     pub join_date: String,
+    #[serde(default)]
+    pub tz_info: Option<TzInfo>,
+    #[serde(default)]
+    pub is_premium: bool,
+    #[serde(default)]
+    pub premium_until: Option<String>,
+    #[serde(default)]
+    pub karma: f64,
+    #[serde(default)]
+    pub karma_trend: Option<String>,
+    #[serde(default)]
+    pub completed_count: i64,
+    #[serde(default)]
+    pub completed_today: i64,
+    #[serde(default)]
+    pub start_page: Option<String>,
+    /// 1 for Monday up to 7 for Sunday.
+    #[serde(default)]
+    pub start_day: i64,
+    #[serde(default)]
+    pub date_format: i64,
+    #[serde(default)]
+    pub time_format: i64,
+    #[serde(default)]
+    pub sort_order: i64,
+    #[serde(default)]
+    pub default_reminder: Option<String>,
+    #[serde(default)]
+    pub features: Extra,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 
@@ -0,0 +1,194 @@
+//! Weekly reviews, the way OmniFocus does them.
+//!
+//! Every project is due for a review some time after its last one, a week
+//! unless configured otherwise. A due project gets a review task, and checking
+//! that task off (or deleting it) counts as reviewing the project and starts
+//! the clock again. When each project was last reviewed is kept in a file of
+//! its own next to the sync state, a project first seen starts its clock then.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use {TaskTree, Node, NodeType, Project};
+use v9::IdMap;
+
+/// The settings of reviews.
+#[derive(Debug, Clone)]
+pub struct ReviewRules {
+    /// How long after its last review a project is due again.
+    pub interval: Duration,
+    /// The interval of single projects, keyed by project id or name. Names
+    /// are compared regardless of case, and a zero interval turns reviews off.
+    pub intervals: BTreeMap<String, Duration>,
+    /// The content of review tasks.
+    pub task: String,
+}
+
+impl Default for ReviewRules {
+    fn default() -> ReviewRules {
+        ReviewRules {
+            interval: Duration::new(7 * 24 * 60 * 60, 0),
+            intervals: BTreeMap::new(),
+            task: "Review".into(),
+        }
+    }
+}
+
+impl ReviewRules {
+    pub fn interval(&self, project: &Project) -> Duration {
+        let (id, name) = (project.id.to_string(), project.name.to_lowercase());
+        self.intervals
+            .get(&id)
+            .or_else(|| {
+                self.intervals
+                    .iter()
+                    .find(|&(key, _)| key.to_lowercase() == name)
+                    .map(|(_, interval)| interval)
+            })
+            .cloned()
+            .unwrap_or(self.interval)
+    }
+}
+
+/// The review of one project.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Review {
+    /// In seconds since the epoch.
+    pub last_reviewed: u64,
+    /// The open review task, if any.
+    pub task_id: Option<usize>,
+}
+
+/// The reviews of all projects of the tree.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ReviewWatch {
+    pub reviews: BTreeMap<usize, Review>,
+}
+
+impl ReviewWatch {
+    /// Look at the projects of `tree` at `now`, in seconds since the epoch,
+    /// and return the ones due for a review task.
+    pub fn update(&mut self, tree: &TaskTree, rules: &ReviewRules, now: u64) -> Vec<usize> {
+        let mut seen = BTreeMap::new();
+        let mut due = vec![];
+        for node in &tree.nodes {
+            self.visit(tree, node, rules, now, &mut seen, &mut due);
+        }
+        // archived or deleted projects are out of the tree
+        self.reviews = seen;
+        due
+    }
+
+    /// Remember the review task created for `project_id`.
+    pub fn task_added(&mut self, project_id: usize, task_id: usize) {
+        if let Some(review) = self.reviews.get_mut(&project_id) {
+            review.task_id = Some(task_id);
+        }
+    }
+
+    fn visit(&self,
+             tree: &TaskTree,
+             node: &Node,
+             rules: &ReviewRules,
+             now: u64,
+             seen: &mut BTreeMap<usize, Review>,
+             due: &mut Vec<usize>) {
+        let project = match node.ntype {
+            NodeType::ProjectNodeType(ref project) => project,
+            NodeType::ItemNodeType(_) | NodeType::SectionNodeType(_) => return,
+        };
+        for child in &node.nodes {
+            self.visit(tree, child, rules, now, seen, due);
+        }
+        if project.extra.get("inbox_project").and_then(|inbox| inbox.as_bool()).unwrap_or(false) {
+            return;
+        }
+        let mut review = self.reviews.get(&project.id).cloned().unwrap_or(Review {
+            last_reviewed: now,
+            task_id: None,
+        });
+        if let Some(task_id) = review.task_id {
+            if tree.item(task_id).map(|item| item.checked == 1).unwrap_or(true) {
+                debug!("Project {} '{}' is reviewed", project.id, project.name);
+                review = Review {
+                    last_reviewed: now,
+                    task_id: None,
+                };
+            }
+        }
+        let interval = rules.interval(project).as_secs();
+        if review.task_id.is_none() && interval > 0 && now >= review.last_reviewed + interval {
+            // an open one from before the reviews were lost is still the task
+            match open_task(node, &rules.task) {
+                Some(task_id) => review.task_id = Some(task_id),
+                None => due.push(project.id),
+            }
+        }
+        seen.insert(project.id, review);
+    }
+
+    /// The reviews keyed by Todoist ids, to be saved.
+    pub fn saved(&self, ids: &IdMap) -> SavedReviews {
+        let reviews = self.reviews
+            .iter()
+            .filter_map(|(&project_id, review)| {
+                ids.object(project_id).ok().map(|project_id| {
+                    (project_id,
+                     SavedReview {
+                        last_reviewed: review.last_reviewed,
+                        task_id: review.task_id.and_then(|id| ids.object(id).ok()),
+                    })
+                })
+            })
+            .collect();
+        SavedReviews {
+            version: REVIEWS_VERSION,
+            reviews: reviews,
+        }
+    }
+
+    /// Take back saved reviews, giving their ids numbers in `ids`.
+    pub fn restore(saved: SavedReviews, ids: &mut IdMap) -> ReviewWatch {
+        let reviews = saved.reviews
+            .into_iter()
+            .map(|(project_id, review)| {
+                (ids.id(&project_id),
+                 Review {
+                    last_reviewed: review.last_reviewed,
+                    task_id: review.task_id.map(|id| ids.id(&id)),
+                })
+            })
+            .collect();
+        ReviewWatch { reviews: reviews }
+    }
+}
+
+fn open_task(project: &Node, task: &str) -> Option<usize> {
+    project.nodes
+        .iter()
+        .filter_map(|child| match child.ntype {
+            NodeType::ItemNodeType(ref item) if item.checked == 0 && item.content.trim() == task => Some(item.id),
+            _ => None,
+        })
+        .next()
+}
+
+/// Bump this only when older review files can no longer be read.
+pub const REVIEWS_VERSION: u64 = 1;
+
+/// The reviews as written to disk. They are kept by Todoist id rather than by
+/// the numbers of the sync state, so they outlive a sync state that is thrown
+/// away, and fields added later get defaults instead of a new version.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SavedReviews {
+    pub version: u64,
+    #[serde(default)]
+    pub reviews: BTreeMap<String, SavedReview>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedReview {
+    pub last_reviewed: u64,
+    #[serde(default)]
+    pub task_id: Option<String>,
+}
@@ -0,0 +1,150 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{from_str, from_value, to_string, Value as JsonValue};
+
+use errors::*;
+use archive::ArchiveWatch;
+use review::{SavedReviews, REVIEWS_VERSION};
+use protocol::{Project, Section, Item, Note, TzInfo, ApiVersion};
+use v9::IdMap;
+
+/// Bump this whenever `SyncState` changes shape, older files are thrown away.
+pub const STATE_VERSION: u64 = 11;
+const STATE_FILE: &'static str = "state.json";
+/// Reviews outlive the sync state, see `SavedReviews`.
+const REVIEWS_FILE: &'static str = "reviews.json";
+
+/// Everything needed to resume incremental syncing after a restart.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncState {
+    pub version: u64,
+    pub api_version: ApiVersion,
+    /// The numbers given to v9 string ids, the cached objects use them.
+    pub ids: IdMap,
+    pub sync_token: String,
+    pub projects: Vec<Project>,
+    pub sections: Vec<Section>,
+    pub items: Vec<Item>,
+    /// The notes giving start dates.
+    pub notes: Vec<Note>,
+    pub tz_info: Option<TzInfo>,
+    pub nextaction_name: String,
+    pub nextaction_id: Option<usize>,
+    pub someday_name: String,
+    pub someday_id: Option<usize>,
+    pub parallel_label: Option<String>,
+    pub parallel_id: Option<usize>,
+    pub sequential_label: Option<String>,
+    pub sequential_id: Option<usize>,
+    pub archive_watch: ArchiveWatch,
+}
+
+pub struct StateStore {
+    dir: PathBuf,
+}
+
+impl StateStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> StateStore {
+        StateStore { dir: dir.as_ref().to_path_buf() }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(STATE_FILE)
+    }
+
+    /// Read the state back. A missing file gives `None`, a corrupt or outdated
+    /// one is removed so that the next save starts over.
+    pub fn load(&self) -> Option<SyncState> {
+        let path = self.path();
+        let mut s = String::new();
+        match File::open(&path) {
+            Ok(mut f) => {
+                if let Err(err) = f.read_to_string(&mut s) {
+                    warn!("Cannot read state file '{}': '{}'", path.display(), err);
+                    return None;
+                }
+            }
+            Err(_) => return None,
+        }
+
+        match self.parse(&s) {
+            Ok(state) => {
+                info!("Resuming from state file '{}'", path.display());
+                Some(state)
+            }
+            Err(err) => {
+                warn!("Discarding state file '{}': '{}'", path.display(), err);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    fn parse(&self, s: &str) -> Result<SyncState> {
+        let value: JsonValue = from_str(s)?;
+        let version = value["version"].as_u64();
+        if version != Some(STATE_VERSION) {
+            return Err(format!("version is {:?}, expected {}", version, STATE_VERSION).into());
+        }
+        Ok(from_value(value)?)
+    }
+
+    /// Write the state through a temporary file, so a crash never leaves half a file behind.
+    pub fn save(&self, state: &SyncState) -> Result<()> {
+        self.write(STATE_FILE, &to_string(state)?)
+    }
+
+    pub fn reviews_path(&self) -> PathBuf {
+        self.dir.join(REVIEWS_FILE)
+    }
+
+    /// Read the reviews back. Unlike the sync state an unreadable file is
+    /// kept aside as `reviews.json.bad` rather than removed.
+    pub fn load_reviews(&self) -> Option<SavedReviews> {
+        let path = self.reviews_path();
+        let mut s = String::new();
+        match File::open(&path) {
+            Ok(mut f) => {
+                if let Err(err) = f.read_to_string(&mut s) {
+                    warn!("Cannot read review file '{}': '{}'", path.display(), err);
+                    return None;
+                }
+            }
+            Err(_) => return None,
+        }
+
+        match self.parse_reviews(&s) {
+            Ok(reviews) => Some(reviews),
+            Err(err) => {
+                let bad = self.dir.join(format!("{}.bad", REVIEWS_FILE));
+                warn!("Moving review file '{}' to '{}': '{}'", path.display(), bad.display(), err);
+                let _ = fs::rename(&path, &bad);
+                None
+            }
+        }
+    }
+
+    fn parse_reviews(&self, s: &str) -> Result<SavedReviews> {
+        let reviews: SavedReviews = from_str(s)?;
+        if reviews.version > REVIEWS_VERSION {
+            return Err(format!("version is {}, expected up to {}", reviews.version, REVIEWS_VERSION).into());
+        }
+        Ok(reviews)
+    }
+
+    pub fn save_reviews(&self, reviews: &SavedReviews) -> Result<()> {
+        self.write(REVIEWS_FILE, &to_string(reviews)?)
+    }
+
+    fn write(&self, file: &str, contents: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(file);
+        let tmp = self.dir.join(format!("{}.tmp", file));
+        File::create(&tmp)?.write_all(contents.as_bytes())?;
+        fs::rename(&tmp, &path)?;
+        debug!("Saved '{}'", path.display());
+        Ok(())
+    }
+}
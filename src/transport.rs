@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::Client;
+use hyper::header::{ContentType, UserAgent, Accept, Headers, qitem};
+use hyper::net::HttpsConnector;
+use hyper_rustls::TlsClient;
+
+use errors::*;
+
+/// A form-encoded POST to the Todoist API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub form: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.form.iter().find(|&&(ref k, _)| k == name).map(|&(_, ref v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn ok<S: Into<String>>(body: S) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_str())
+    }
+}
+
+/// The thing `Todoist` talks through. The default one goes over the network,
+/// tests can plug in anything that answers like the sync endpoint.
+pub trait Transport: Send {
+    fn post(&self, request: &HttpRequest) -> Result<HttpResponse>;
+}
+
+pub struct HyperTransport {
+    client: Client,
+}
+
+impl HyperTransport {
+    pub fn new() -> HyperTransport {
+        let mut client = Client::with_connector(HttpsConnector::new(TlsClient::new()));
+        client.set_read_timeout(Some(Duration::from_secs(30)));
+        client.set_write_timeout(Some(Duration::from_secs(30)));
+        HyperTransport { client: client }
+    }
+}
+
+impl Transport for HyperTransport {
+    fn post(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        use url::form_urlencoded;
+
+        let dt = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(request.form.iter().map(|&(ref k, ref v)| (k.as_str(), v.as_str())))
+            .finish();
+
+        let mut headers = Headers::new();
+        headers.set(ContentType(mime!(Application/WwwFormUrlEncoded; Charset=Utf8)));
+        headers.set(UserAgent("curl/7.43.0".into()));
+        headers.set(Accept(vec![qitem(mime!(_/_))]));
+        for &(ref name, ref value) in &request.headers {
+            headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+        }
+
+        let mut resp = self.client
+            .post(&request.url)
+            .headers(headers)
+            .body(&dt)
+            .send()?;
+
+        let mut body = String::new();
+        resp.read_to_string(&mut body)?;
+
+        Ok(HttpResponse {
+            status: resp.status.to_u16(),
+            headers: resp.headers
+                .iter()
+                .map(|h| (h.name().to_string(), h.value_string()))
+                .collect(),
+            body: body,
+        })
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    responses: VecDeque<HttpResponse>,
+    requests: Vec<HttpRequest>,
+}
+
+/// Replays canned responses in order and records every request it sees.
+/// Clones share the same queue, so a test can keep one handle for inspection.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> MemoryTransport {
+        MemoryTransport::default()
+    }
+
+    pub fn push_response(&self, response: HttpResponse) {
+        self.state.lock().unwrap().responses.push_back(response);
+    }
+
+    pub fn push_json<S: Into<String>>(&self, body: S) {
+        self.push_response(HttpResponse::ok(body));
+    }
+
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn post(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(request.clone());
+        state.responses
+            .pop_front()
+            .ok_or_else(|| format!("no canned response left for '{:?}'", request.form).into())
+    }
+}
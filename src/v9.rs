@@ -0,0 +1,557 @@
+//! The Sync API v9 wire format, translated to and from the model the rest of
+//! the crate works with.
+//!
+//! v9 hands out string ids and refers to labels by name. Numeric ids are kept
+//! as they are, every other string (and every label name) gets a number from
+//! an `IdMap`, which has to outlive the process together with the sync state.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value as JsonValue;
+
+use errors::*;
+use command::{Command, CommandKind, CommandId, TempId, ObjectId, CommandStatus};
+use protocol::{Project, Section, Item, Label, Note, User, TzInfo, TodoistResponse, CommandResponse, Extra};
+
+pub const ENDPOINT: &'static str = "https://api.todoist.com/sync/v9/sync";
+
+/// Ids from here on are handed out by an `IdMap` rather than parsed.
+const INTERNED: usize = ::std::usize::MAX / 2 + 1;
+
+/// Numbers for the strings v9 uses as ids and label names.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IdMap {
+    strings: BTreeMap<usize, String>,
+    /// Numbers are never handed out twice, even once their string is gone.
+    next: usize,
+    /// The other way round, rebuilt from `strings` after a restore.
+    #[serde(skip)]
+    numbers: BTreeMap<String, usize>,
+}
+
+impl IdMap {
+    /// The number of an object id, numeric ids stand for themselves.
+    pub fn id(&mut self, s: &str) -> usize {
+        match s.parse::<usize>() {
+            Ok(id) if id < INTERNED => id,
+            _ => self.intern(s),
+        }
+    }
+
+    /// The number of a label, which items only know by name.
+    pub fn label(&mut self, name: &str) -> usize {
+        self.intern(name)
+    }
+
+    /// The string an object id number stands for.
+    pub fn object(&self, id: usize) -> Result<String> {
+        if id < INTERNED {
+            Ok(id.to_string())
+        } else {
+            self.string(id)
+        }
+    }
+
+    /// The string a label or interned id number stands for.
+    pub fn string(&self, id: usize) -> Result<String> {
+        self.strings.get(&id).cloned().ok_or_else(|| format!("no v9 id known for {}", id).into())
+    }
+
+    /// How many strings have a number.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Forget the string of `id`, once the object it stands for is gone.
+    pub fn forget(&mut self, id: usize) {
+        if let Some(s) = self.strings.remove(&id) {
+            self.numbers.remove(&s);
+        }
+    }
+
+    /// Forget every string whose number isn't in `used`.
+    pub fn retain(&mut self, used: &BTreeSet<usize>) {
+        let gone: Vec<usize> = self.strings.keys().filter(|id| !used.contains(id)).cloned().collect();
+        for id in gone {
+            self.forget(id);
+        }
+    }
+
+    fn intern(&mut self, s: &str) -> usize {
+        if self.numbers.len() != self.strings.len() {
+            self.numbers = self.strings.iter().map(|(&id, s)| (s.clone(), id)).collect();
+        }
+        if let Some(&id) = self.numbers.get(s) {
+            return id;
+        }
+        let id = INTERNED + self.next;
+        self.next += 1;
+        self.strings.insert(id, s.into());
+        self.numbers.insert(s.into(), id);
+        id
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Response {
+    projects: Option<Vec<V9Project>>,
+    #[serde(default)]
+    sections: Option<Vec<V9Section>>,
+    notes: Option<Vec<V9Note>>,
+    items: Option<Vec<V9Item>>,
+    labels: Option<Vec<V9Label>>,
+    user: Option<V9User>,
+    full_sync: bool,
+    sync_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9Project {
+    id: String,
+    name: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+    #[serde(default)]
+    child_order: usize,
+    #[serde(default)]
+    is_archived: bool,
+    #[serde(default)]
+    is_deleted: bool,
+    #[serde(default)]
+    inbox_project: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9Section {
+    id: String,
+    name: String,
+    project_id: String,
+    #[serde(default)]
+    section_order: usize,
+    #[serde(default)]
+    is_archived: bool,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9Item {
+    id: String,
+    project_id: String,
+    #[serde(default)]
+    section_id: Option<String>,
+    content: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+    #[serde(default)]
+    child_order: usize,
+    #[serde(default)]
+    priority: usize,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    checked: bool,
+    #[serde(default)]
+    is_deleted: bool,
+    #[serde(default)]
+    added_at: Option<String>,
+    #[serde(default)]
+    due: Option<V9Due>,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9Due {
+    string: String,
+    #[serde(default)]
+    lang: Option<String>,
+    #[serde(default)]
+    is_recurring: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9Label {
+    id: String,
+    name: String,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9Note {
+    id: String,
+    item_id: String,
+    content: String,
+    #[serde(default)]
+    posted_at: Option<String>,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct V9User {
+    id: String,
+    #[serde(default)]
+    token: Option<String>,
+    email: String,
+    full_name: String,
+    inbox_project_id: String,
+    #[serde(default)]
+    joined_at: Option<String>,
+    #[serde(default)]
+    tz_info: Option<TzInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CommandResult {
+    #[serde(default)]
+    sync_status: BTreeMap<CommandId, CommandStatus>,
+    #[serde(default)]
+    temp_id_mapping: BTreeMap<TempId, String>,
+}
+
+fn flag(b: bool) -> usize {
+    if b { 1 } else { 0 }
+}
+
+/// Translate a v9 sync answer into the v7 shaped model.
+pub fn response(ids: &mut IdMap, resp: Response) -> TodoistResponse {
+    let projects = resp.projects.map(|projects| {
+        projects.into_iter()
+            .map(|p| {
+                let mut extra = Extra::new();
+                // reviews and archiving leave the inbox alone, as with v7
+                if p.inbox_project {
+                    extra.insert("inbox_project".into(), JsonValue::Bool(true));
+                }
+                Project {
+                    id: ids.id(&p.id),
+                    name: p.name,
+                    item_order: p.child_order,
+                    indent: 1,
+                    parent_id: p.parent_id.map(|parent| ids.id(&parent)),
+                    is_archived: flag(p.is_archived),
+                    is_deleted: flag(p.is_deleted),
+                    extra: extra,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    });
+    let sections = resp.sections.map(|sections| {
+        sections.into_iter()
+            .map(|s| {
+                Section {
+                    id: ids.id(&s.id),
+                    name: s.name,
+                    project_id: ids.id(&s.project_id),
+                    section_order: s.section_order,
+                    is_archived: flag(s.is_archived),
+                    is_deleted: flag(s.is_deleted),
+                }
+            })
+            .collect()
+    });
+    let items = resp.items.map(|items| {
+        items.into_iter()
+            .map(|i| {
+                Item {
+                    id: ids.id(&i.id),
+                    project_id: ids.id(&i.project_id),
+                    section_id: i.section_id.map(|section| ids.id(&section)),
+                    content: i.content,
+                    indent: 1,
+                    item_order: i.child_order,
+                    parent_id: i.parent_id.map(|parent| ids.id(&parent)),
+                    date_added: i.added_at.unwrap_or_default(),
+                    priority: i.priority,
+                    all_day: false,
+                    labels: i.labels.iter().map(|name| ids.label(name)).collect(),
+                    is_deleted: flag(i.is_deleted),
+                    is_archived: 0,
+                    checked: flag(i.checked),
+                    in_history: 0,
+                    date_string: i.due.as_ref().map(|due| due.string.clone()),
+                    is_recurring: i.due.as_ref().map(|due| due.is_recurring),
+                    date_lang: i.due.and_then(|due| due.lang),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    });
+    // items refer to labels by name, so the label's number comes from its name too
+    let labels = resp.labels.map(|labels| {
+        labels.into_iter()
+            .map(|l| {
+                debug!("label '{}' has v9 id {}", l.name, l.id);
+                Label {
+                    id: ids.label(&l.name),
+                    name: l.name,
+                    is_deleted: flag(l.is_deleted),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    });
+    let notes = resp.notes.map(|notes| {
+        notes.into_iter()
+            .map(|n| {
+                Note {
+                    id: ids.id(&n.id),
+                    item_id: ids.id(&n.item_id),
+                    project_id: 0,
+                    content: n.content,
+                    posted: n.posted_at.unwrap_or_default(),
+                    is_deleted: flag(n.is_deleted),
+                    is_archived: 0,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    });
+    let user = resp.user.map(|u| translate_user(ids, u));
+
+    let response = TodoistResponse {
+        projects: projects,
+        sections: sections,
+        notes: notes,
+        items: items,
+        labels: labels,
+        user: user,
+        full_sync: resp.full_sync,
+        sync_token: resp.sync_token,
+        ..Default::default()
+    };
+    prune(ids, &response);
+    response
+}
+
+/// The user of a response that asked for nothing else. Such a response says
+/// `full_sync` without listing anything, so it must not prune the ids.
+pub fn user(ids: &mut IdMap, resp: Response) -> Option<User> {
+    resp.user.map(|u| translate_user(ids, u))
+}
+
+fn translate_user(ids: &mut IdMap, u: V9User) -> User {
+    User {
+        id: u.id.parse().unwrap_or_default(),
+        token: u.token,
+        email: u.email,
+        full_name: u.full_name,
+        inbox_project: ids.id(&u.inbox_project_id) as i64,
+        join_date: u.joined_at.unwrap_or_default(),
+        tz_info: u.tz_info,
+        ..Default::default()
+    }
+}
+
+/// Keep the map from growing forever: the ids of deleted objects go, and a
+/// full sync drops every id it doesn't mention.
+fn prune(ids: &mut IdMap, response: &TodoistResponse) {
+    let mut used = BTreeSet::new();
+    let mut deleted = vec![];
+    for project in response.projects.iter().flat_map(|p| p.iter()) {
+        used.insert(project.id);
+        used.extend(project.parent_id);
+        if project.is_deleted == 1 {
+            deleted.push(project.id);
+        }
+    }
+    for section in response.sections.iter().flat_map(|s| s.iter()) {
+        used.extend(vec![section.id, section.project_id]);
+        if section.is_deleted == 1 {
+            deleted.push(section.id);
+        }
+    }
+    for item in response.items.iter().flat_map(|i| i.iter()) {
+        used.extend(vec![item.id, item.project_id]);
+        used.extend(item.section_id);
+        used.extend(item.parent_id);
+        used.extend(item.labels.iter().cloned());
+        if item.is_deleted == 1 {
+            deleted.push(item.id);
+        }
+    }
+    // a label's number comes from its name, which may well be used again
+    used.extend(response.labels.iter().flat_map(|l| l.iter()).map(|label| label.id));
+    for note in response.notes.iter().flat_map(|n| n.iter()) {
+        used.extend(vec![note.id, note.item_id]);
+        if note.is_deleted == 1 {
+            deleted.push(note.id);
+        }
+    }
+    used.extend(response.user.iter().map(|user| user.inbox_project as usize));
+    if response.full_sync {
+        ids.retain(&used);
+    }
+    for id in deleted {
+        ids.forget(id);
+    }
+}
+
+/// Spell out `commands` the way v9 expects them: string ids, labels by name,
+/// one object per command and the renamed `item_close` and reorders.
+pub fn commands(ids: &IdMap, commands: &[Command]) -> Result<JsonValue> {
+    // a label created in the same request is referred to by its name
+    let created: BTreeMap<TempId, &str> = commands.iter()
+        .filter_map(|c| match (c.temp_id, &c.kind) {
+            (Some(temp_id), &CommandKind::LabelAdd { ref name }) => Some((temp_id, name.as_str())),
+            _ => None,
+        })
+        .collect();
+    let object = |id: &ObjectId| -> Result<String> {
+        match *id {
+            ObjectId::Id(id) => ids.object(id),
+            ObjectId::Temp(temp_id) => Ok(temp_id.to_string()),
+        }
+    };
+    let label = |id: &ObjectId| -> Result<String> {
+        match *id {
+            ObjectId::Id(id) => ids.string(id),
+            ObjectId::Temp(temp_id) => {
+                created.get(&temp_id)
+                    .map(|name| name.to_string())
+                    .ok_or_else(|| format!("label {} isn't created in this request", temp_id).into())
+            }
+        }
+    };
+
+    let mut out = vec![];
+    for command in commands {
+        let (kind, args) = match command.kind {
+            CommandKind::LabelAdd { ref name } => ("label_add", json!({ "name": name })),
+            CommandKind::ItemUpdate { ref id, ref labels } => {
+                let mut args = json!({ "id": object(id)? });
+                if let Some(ref labels) = *labels {
+                    let names = labels.iter().map(&label).collect::<Result<Vec<String>>>()?;
+                    args["labels"] = json!(names);
+                }
+                ("item_update", args)
+            }
+            CommandKind::ItemAdd { ref content,
+                                   ref project_id,
+                                   ref parent_id,
+                                   ref item_order,
+                                   ref labels,
+                                   ref priority,
+                                   ref date_string,
+                                   .. } => {
+                // there are no indents in v9, nesting goes by parent_id
+                let mut args = json!({ "content": content, "project_id": object(project_id)? });
+                if let Some(ref parent_id) = *parent_id {
+                    args["parent_id"] = json!(object(parent_id)?);
+                }
+                if let Some(order) = *item_order {
+                    args["child_order"] = json!(order);
+                }
+                if let Some(ref labels) = *labels {
+                    let names = labels.iter().map(&label).collect::<Result<Vec<String>>>()?;
+                    args["labels"] = json!(names);
+                }
+                if let Some(priority) = *priority {
+                    args["priority"] = json!(priority);
+                }
+                if let Some(ref date_string) = *date_string {
+                    args["due"] = json!({ "string": date_string });
+                }
+                ("item_add", args)
+            }
+            CommandKind::ItemMove { ref project_items, ref to_project } => {
+                let id = one(project_items.values().flat_map(|ids| ids.iter()), "item_move")?;
+                ("item_move", json!({ "id": object(id)?, "project_id": object(to_project)? }))
+            }
+            CommandKind::ItemDelete { ref ids } => {
+                ("item_delete", json!({ "id": object(one(ids, "item_delete")?)? }))
+            }
+            CommandKind::CloseItem { ref id } => ("item_close", json!({ "id": object(id)? })),
+            CommandKind::ItemUncomplete { ref ids } => {
+                ("item_uncomplete", json!({ "id": object(one(ids, "item_uncomplete")?)? }))
+            }
+            CommandKind::ItemUpdateOrdersIndents { ref ids_to_orders_indents } => {
+                ("item_reorder", json!({ "items": reorder(ids_to_orders_indents, &object)? }))
+            }
+            CommandKind::ProjectAdd { ref name, ref parent_id, ref item_order, .. } => {
+                let mut args = json!({ "name": name });
+                if let Some(ref parent_id) = *parent_id {
+                    args["parent_id"] = json!(object(parent_id)?);
+                }
+                if let Some(order) = *item_order {
+                    args["child_order"] = json!(order);
+                }
+                ("project_add", args)
+            }
+            CommandKind::ProjectUpdate { ref id, ref name, ref collapsed } => {
+                let mut args = json!({ "id": object(id)? });
+                if let Some(ref name) = *name {
+                    args["name"] = json!(name);
+                }
+                if let Some(collapsed) = *collapsed {
+                    args["collapsed"] = json!(collapsed == 1);
+                }
+                ("project_update", args)
+            }
+            CommandKind::ProjectArchive { ref ids } => {
+                ("project_archive", json!({ "id": object(one(ids, "project_archive")?)? }))
+            }
+            CommandKind::ProjectUpdateOrdersIndents { ref ids_to_orders_indents } => {
+                ("project_reorder", json!({ "projects": reorder(ids_to_orders_indents, &object)? }))
+            }
+            CommandKind::NoteAdd { ref item_id, ref content } => {
+                ("note_add", json!({ "item_id": object(item_id)?, "content": content }))
+            }
+        };
+        let mut value = json!({ "type": kind, "args": args, "uuid": command.uuid });
+        if let Some(temp_id) = command.temp_id {
+            value["temp_id"] = json!(temp_id);
+        }
+        out.push(value);
+    }
+    Ok(JsonValue::Array(out))
+}
+
+/// v9 takes one object per command where v7 took a list.
+fn one<'a, I>(ids: I, command: &str) -> Result<&'a ObjectId>
+    where I: IntoIterator<Item = &'a ObjectId>
+{
+    let ids: Vec<&ObjectId> = ids.into_iter().collect();
+    match ids.len() {
+        1 => Ok(ids[0]),
+        _ => Err(format!("v9 {} takes one object per command", command).into()),
+    }
+}
+
+/// `[{"id": ..., "child_order": ...}]`, there are no indents in v9.
+fn reorder<F>(orders: &BTreeMap<ObjectId, (usize, usize)>, object: &F) -> Result<Vec<JsonValue>>
+    where F: Fn(&ObjectId) -> Result<String>
+{
+    orders.iter()
+        .map(|(id, &(order, _))| Ok(json!({ "id": object(id)?, "child_order": order })))
+        .collect()
+}
+
+/// Translate the answer to `sent`. The real ids of created labels are
+/// replaced by the numbers of their names, as everywhere else.
+pub fn command_response(ids: &mut IdMap, sent: &[Command], resp: CommandResult) -> CommandResponse {
+    let mut temp_id_mapping = BTreeMap::new();
+    for (temp_id, real) in resp.temp_id_mapping {
+        let label = sent.iter()
+            .filter(|c| c.temp_id == Some(temp_id))
+            .filter_map(|c| match c.kind {
+                CommandKind::LabelAdd { ref name } => Some(name),
+                _ => None,
+            })
+            .next();
+        let id = match label {
+            Some(name) => ids.label(name),
+            None => ids.id(&real),
+        };
+        temp_id_mapping.insert(temp_id, id);
+    }
+    CommandResponse {
+        sync_status: resp.sync_status,
+        temp_id_mapping: temp_id_mapping,
+    }
+}
@@ -0,0 +1,147 @@
+//! Webhook mode: Todoist posts an event whenever an item changes, and a round
+//! runs as soon as the burst of deliveries is over instead of at the next tick
+//! of the poll loop.
+//!
+//! Deliveries are signed with the client secret of the Todoist app the webhook
+//! is registered for, anything without a valid signature is turned away.
+
+use std::io::Read;
+use std::str;
+use std::sync::Mutex;
+use std::sync::mpsc::{Sender, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use base64;
+use hmac::{Hmac, Mac};
+use hyper::method::Method;
+use hyper::server::{Handler, Request, Response};
+use hyper::status::StatusCode;
+use serde_json::{self, Value as JsonValue};
+use sha2::Sha256;
+
+/// The header carrying the signature of a delivery.
+pub const SIGNATURE_HEADER: &'static str = "X-Todoist-Hmac-SHA256";
+/// The events that may change which tasks are next actions.
+pub const EVENTS: [&'static str; 3] = ["item:added", "item:updated", "item:completed"];
+
+/// Whether `signature` is the base64 encoded HMAC-SHA256 of `body` under `secret`.
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let code = match base64::decode(signature.trim()) {
+        Ok(code) => code,
+        Err(_) => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_varkey(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.input(body);
+    mac.verify(&code).is_ok()
+}
+
+/// A webhook delivery. Only the name of the event matters, the round that
+/// follows syncs whatever changed.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Delivery {
+    pub event_name: String,
+    /// A number in v7, a string in v9.
+    #[serde(default)]
+    pub user_id: JsonValue,
+    #[serde(default)]
+    pub event_data: JsonValue,
+}
+
+impl Delivery {
+    pub fn is_relevant(&self) -> bool {
+        EVENTS.contains(&self.event_name.as_str())
+    }
+}
+
+/// Checks deliveries and passes the relevant ones on to the loop in `serve`.
+pub struct WebhookHandler {
+    secret: String,
+    events: Mutex<Sender<Delivery>>,
+}
+
+impl WebhookHandler {
+    pub fn new(secret: &str, events: Sender<Delivery>) -> WebhookHandler {
+        WebhookHandler {
+            secret: secret.into(),
+            events: Mutex::new(events),
+        }
+    }
+
+    /// Take a delivery and answer with the HTTP status to send back.
+    /// Events we don't care about are still acknowledged, so that Todoist
+    /// doesn't send them again.
+    pub fn receive(&self, signature: Option<&str>, body: &[u8]) -> u16 {
+        if !signature.map(|s| verify(&self.secret, body, s)).unwrap_or(false) {
+            warn!("Dropping a webhook delivery with a bad signature");
+            return 401;
+        }
+        let delivery: Delivery = match serde_json::from_slice(body) {
+            Ok(delivery) => delivery,
+            Err(err) => {
+                warn!("Dropping a malformed webhook delivery: '{}'", err);
+                return 400;
+            }
+        };
+        if delivery.is_relevant() {
+            debug!("Received {}", delivery.event_name);
+            if self.events.lock().unwrap().send(delivery).is_err() {
+                return 503;
+            }
+        }
+        200
+    }
+}
+
+impl Handler for WebhookHandler {
+    fn handle(&self, mut req: Request, mut res: Response) {
+        let status = if req.method != Method::Post {
+            405
+        } else {
+            let signature = req.headers
+                .get_raw(SIGNATURE_HEADER)
+                .and_then(|values| values.first())
+                .and_then(|value| str::from_utf8(value).ok())
+                .map(|value| value.to_string());
+            let mut body = vec![];
+            match req.read_to_end(&mut body) {
+                Ok(_) => self.receive(signature.as_ref().map(|s| s.as_str()), &body),
+                Err(_) => 400,
+            }
+        };
+        *res.status_mut() = StatusCode::from_u16(status);
+        let _ = res.send(b"");
+    }
+}
+
+/// Why `wait` returned.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    /// This many deliveries came in and then nothing for the debounce period.
+    Events(usize),
+    /// Nothing came in for the fallback period.
+    Fallback,
+    /// The server is gone.
+    Closed,
+}
+
+/// Block until deliveries stop coming in for `debounce`, or until `fallback`
+/// passes without any. A burst never holds the round back beyond `fallback`.
+pub fn wait(events: &Receiver<Delivery>, debounce: Duration, fallback: Duration) -> Wakeup {
+    match events.recv_timeout(fallback) {
+        Ok(_) => {}
+        Err(RecvTimeoutError::Timeout) => return Wakeup::Fallback,
+        Err(RecvTimeoutError::Disconnected) => return Wakeup::Closed,
+    }
+    let start = Instant::now();
+    let mut count = 1;
+    while start.elapsed() < fallback {
+        match events.recv_timeout(debounce) {
+            Ok(_) => count += 1,
+            Err(_) => break,
+        }
+    }
+    Wakeup::Events(count)
+}
@@ -0,0 +1,116 @@
+extern crate nextaction;
+use std::time::Duration;
+use nextaction::{NextAction, Todoist, FakeTodoist, TaskTree, Project, Item, ArchiveRules, ArchiveWatch};
+
+mod common;
+use common::{project, task};
+
+const DAY: u64 = 24 * 60 * 60;
+
+fn tree(projects: &[Project], items: &[Item]) -> TaskTree {
+    TaskTree::build(projects, items).unwrap()
+}
+
+#[test]
+fn grace_period() {
+    let rules = ArchiveRules::default();
+    let mut watch = ArchiveWatch::default();
+    let projects = [project(1, "Trip:")];
+    assert!(watch.update(&tree(&projects, &[task(10, 1, false)]), &rules, 0).is_empty());
+
+    let done = tree(&projects, &[task(10, 1, true)]);
+    assert!(watch.update(&done, &rules, 100).is_empty());
+    assert!(watch.update(&done, &rules, 100 + DAY - 1).is_empty());
+    assert_eq!(watch.update(&done, &rules, 100 + DAY), vec![1]);
+
+    // reopening a task starts over
+    let mut watch = ArchiveWatch::default();
+    watch.update(&tree(&projects, &[task(10, 1, false)]), &rules, 0);
+    watch.update(&done, &rules, 100);
+    watch.update(&tree(&projects, &[task(10, 1, false)]), &rules, 200);
+    assert!(watch.update(&done, &rules, 100 + DAY).is_empty());
+}
+
+#[test]
+fn projects_never_seen_open_stay() {
+    let rules = ArchiveRules {
+        grace: Duration::new(0, 0),
+        ..Default::default()
+    };
+    let mut watch = ArchiveWatch::default();
+    let projects = [project(1, "Trip:"), project(2, "Empty")];
+    assert!(watch.update(&tree(&projects, &[task(10, 1, true)]), &rules, 0).is_empty());
+    assert!(watch.update(&tree(&projects, &[task(10, 1, true)]), &rules, DAY).is_empty());
+}
+
+#[test]
+fn excluded_projects_stay() {
+    let rules = ArchiveRules {
+        grace: Duration::new(0, 0),
+        exclude: vec!["inbox".into(), "2".into()],
+    };
+    let mut watch = ArchiveWatch::default();
+    let projects = [project(1, "Inbox"), project(2, "Someday"), project(3, "Trip")];
+    let open = [task(10, 1, false), task(11, 2, false), task(12, 3, false)];
+    let done = [task(10, 1, true), task(11, 2, true), task(12, 3, true)];
+    watch.update(&tree(&projects, &open), &rules, 0);
+    assert_eq!(watch.update(&tree(&projects, &done), &rules, 0), vec![3]);
+}
+
+#[test]
+fn sub_projects_go_first() {
+    let rules = ArchiveRules {
+        grace: Duration::new(0, 0),
+        ..Default::default()
+    };
+    let mut watch = ArchiveWatch::default();
+    let projects = [project(1, "House"), Project { indent: 2, ..project(2, "Kitchen") }];
+    watch.update(&tree(&projects, &[task(10, 1, false), task(11, 2, false)]), &rules, 0);
+    assert_eq!(watch.update(&tree(&projects, &[task(10, 1, true), task(11, 2, true)]), &rules, 0),
+               vec![2]);
+    // the next round no longer has the kitchen
+    assert_eq!(watch.update(&tree(&projects[..1], &[task(10, 1, true)]), &rules, 0), vec![1]);
+}
+
+#[test]
+fn finished_projects_are_archived() {
+    let fake = FakeTodoist::new();
+    let trip = fake.add_project("Trip:", 1);
+    let flights = fake.add_item(trip, "book flights", 1);
+    let agenda = fake.add_project("Agenda-", 1);
+    fake.add_item(agenda, "call Ann", 1);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.archive = Some(ArchiveRules {
+        grace: Duration::new(0, 0),
+        ..Default::default()
+    });
+    assert!(na.step().unwrap().archives.is_empty());
+
+    fake.complete_item(flights);
+    let plan = na.step().unwrap();
+    assert_eq!(plan.archives.iter().map(|a| a.project_id).collect::<Vec<_>>(), vec![trip]);
+    assert_eq!(fake.project(trip).unwrap().is_archived, 1);
+    assert_eq!(fake.project(agenda).unwrap().is_archived, 0);
+}
+
+#[test]
+fn dry_runs_leave_the_watch_alone() {
+    let fake = FakeTodoist::new();
+    let trip = fake.add_project("Trip:", 1);
+    let flights = fake.add_item(trip, "book flights", 1);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.archive = Some(ArchiveRules {
+        grace: Duration::new(0, 0),
+        ..Default::default()
+    });
+    na.dry_run = true;
+    na.step().unwrap();
+    fake.complete_item(flights);
+    // only a real round counts as having seen the project open
+    assert!(na.step().unwrap().archives.is_empty());
+    na.dry_run = false;
+    assert!(na.step().unwrap().archives.is_empty());
+    assert_eq!(fake.project(trip).unwrap().is_archived, 0);
+}
@@ -0,0 +1,27 @@
+extern crate nextaction;
+
+use std::time::Duration;
+use nextaction::Backoff;
+
+#[test]
+fn delays_grow_up_to_the_cap() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+    let first = backoff.fail(None);
+    assert!(first >= Duration::from_secs(1) && first <= Duration::from_secs(2));
+    let second = backoff.fail(None);
+    assert!(second >= Duration::from_secs(2) && second <= Duration::from_secs(4));
+    for _ in 0..10 {
+        assert!(backoff.fail(None) <= Duration::from_secs(10));
+    }
+    assert!(backoff.fail(None) >= Duration::from_secs(5));
+    assert_eq!(backoff.failures(), 13);
+
+    backoff.reset();
+    assert!(backoff.fail(None) <= Duration::from_secs(2));
+}
+
+#[test]
+fn retry_after_is_a_lower_bound() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+    assert_eq!(backoff.fail(Some(Duration::from_secs(90))), Duration::from_secs(90));
+}
@@ -0,0 +1,246 @@
+extern crate nextaction;
+extern crate serde_json;
+
+use std::time::Duration;
+use serde_json::to_value;
+use nextaction::{Todoist, MemoryTransport, FakeTodoist, Command, CommandKind, CommandStatus, CommandResponse, ObjectId};
+use nextaction::transport::HttpResponse;
+
+#[test]
+fn wire_format() {
+    let mut todoist = Todoist::with_transport("offline", MemoryTransport::new());
+    let mut m = todoist.manager();
+    let (temp_id, uuid) = m.add_label("Waiting");
+    let update = m.set_item_label(7, vec![1, 2]);
+
+    assert_eq!(to_value(&m.commands()[0]).unwrap(),
+               json(&format!(r#"{{"type": "label_add", "args": {{"name": "Waiting"}},
+                                  "uuid": "{}", "temp_id": "{}"}}"#,
+                             uuid,
+                             temp_id)));
+    assert_eq!(to_value(&m.commands()[1]).unwrap(),
+               json(&format!(r#"{{"type": "item_update", "args": {{"id": 7, "labels": [1, 2]}},
+                                  "uuid": "{}"}}"#,
+                             update)));
+}
+
+#[test]
+fn temp_ids_as_arguments() {
+    let (temp_id, _) = {
+        let command = Command::create(CommandKind::LabelAdd { name: "Waiting".into() });
+        (command.temp_id.unwrap(), command.uuid)
+    };
+    let command = Command::new(CommandKind::ItemUpdate {
+        id: ObjectId::Id(7),
+        labels: Some(vec![ObjectId::Id(1), ObjectId::Temp(temp_id)]),
+    });
+    let value = to_value(&command).unwrap();
+    assert_eq!(value["args"]["labels"], json(&format!(r#"[1, "{}"]"#, temp_id)));
+}
+
+#[test]
+fn coalesce_item_updates() {
+    let mut todoist = Todoist::with_transport("offline", MemoryTransport::new());
+    let mut m = todoist.manager();
+    m.set_item_label(7, vec![1]);
+    m.complete_item(8);
+    let last = m.set_item_label(7, vec![2]);
+    m.set_item_label(9, vec![1]);
+    m.coalesce();
+
+    let commands = m.commands();
+    assert_eq!(commands.len(), 3);
+    assert_eq!(commands[0].kind, CommandKind::CloseItem { id: ObjectId::Id(8) });
+    assert_eq!(commands[1].uuid, last);
+    assert_eq!(commands[1].kind,
+               CommandKind::ItemUpdate {
+                   id: ObjectId::Id(7),
+                   labels: Some(vec![ObjectId::Id(2)]),
+               });
+}
+
+fn json(s: &str) -> serde_json::Value {
+    serde_json::from_str(s).unwrap()
+}
+
+#[test]
+fn transient_failures_are_retried() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Home", 1);
+    let item = fake.add_item(project, "dishes", 1);
+    fake.fail_next_commands(1, 503);
+
+    let mut todoist = Todoist::with_transport("offline", fake.clone());
+    todoist.set_retry(3, Duration::from_secs(0));
+    let mut m = todoist.manager();
+    let uuid = m.set_item_label(item, vec![5]);
+    let result = m.flush().unwrap();
+
+    assert!(result.is_ok(&uuid));
+    assert!(result.failures().is_empty());
+    assert_eq!(fake.item(item).unwrap().labels, vec![5]);
+    // the same command went out twice
+    let sent: Vec<String> = fake.commands().iter().map(|c| c["uuid"].as_str().unwrap().to_string()).collect();
+    assert_eq!(sent, vec![uuid.to_string(), uuid.to_string()]);
+}
+
+#[test]
+fn permanent_failures_are_reported() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Home", 1);
+    let item = fake.add_item(project, "dishes", 1);
+
+    let mut todoist = Todoist::with_transport("offline", fake.clone());
+    todoist.set_retry(3, Duration::from_secs(0));
+    let mut m = todoist.manager();
+    let missing = m.complete_item(4242);
+    let fine = m.complete_item(item);
+    let result = m.flush().unwrap();
+
+    assert!(result.is_ok(&fine));
+    match result.status(&missing) {
+        Some(&CommandStatus::Failed(ref err)) => assert_eq!(err.error, "Item not found"),
+        other => panic!("unexpected status {:?}", other),
+    }
+    assert_eq!(result.failures().len(), 1);
+    assert_eq!(fake.commands().len(), 2);
+}
+
+#[test]
+fn retries_give_up() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Home", 1);
+    let item = fake.add_item(project, "dishes", 1);
+    fake.fail_next_commands(5, 429);
+
+    let mut todoist = Todoist::with_transport("offline", fake.clone());
+    todoist.set_retry(2, Duration::from_secs(0));
+    let mut m = todoist.manager();
+    let uuid = m.complete_item(item);
+    let result = m.flush().unwrap();
+
+    assert!(!result.is_ok(&uuid));
+    assert!(result.failures()[0].1.is_transient());
+    assert_eq!(fake.commands().len(), 2);
+}
+
+#[test]
+fn large_queues_go_out_in_batches() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Home", 1);
+    let items: Vec<usize> = (0..7).map(|n| fake.add_item(project, &format!("chore {}", n), 1)).collect();
+    fake.set_command_limit(3);
+
+    let mut todoist = Todoist::with_transport("offline", fake.clone());
+    todoist.set_batch_size(3);
+    let mut m = todoist.manager();
+    let uuids: Vec<_> = items.iter().map(|&item| m.complete_item(item)).collect();
+    let result = m.flush().unwrap();
+
+    assert!(uuids.iter().all(|uuid| result.is_ok(uuid)));
+    assert_eq!(fake.requests().len(), 3);
+    assert!(items.iter().all(|&item| fake.item(item).unwrap().checked == 1));
+}
+
+#[test]
+fn temp_ids_carry_over_batches() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Home", 1);
+    let item = fake.add_item(project, "dishes", 1);
+
+    let mut todoist = Todoist::with_transport("offline", fake.clone());
+    todoist.set_batch_size(1);
+    let mut m = todoist.manager();
+    let (temp_id, _) = m.add_label("Waiting");
+    let update = m.push(CommandKind::ItemUpdate {
+        id: item.into(),
+        labels: Some(vec![temp_id.into()]),
+    });
+    let result = m.flush().unwrap();
+
+    assert!(result.is_ok(&update));
+    let label = result.id_of(&temp_id).unwrap();
+    assert_eq!(fake.item(item).unwrap().labels, vec![label]);
+    // the second request carried the real id
+    assert_eq!(fake.commands()[1]["args"]["labels"], json(&format!("[{}]", label)));
+}
+
+#[test]
+fn failed_batches_keep_earlier_results() {
+    let transport = MemoryTransport::new();
+    let mut todoist = Todoist::with_transport("offline", transport.clone());
+    todoist.set_batch_size(1);
+    let mut m = todoist.manager();
+    let (temp_id, uuid) = m.add_item(1, "Review");
+    m.complete_item(7);
+    transport.push_json(format!(r#"{{"sync_status": {{"{}": "ok"}}, "temp_id_mapping": {{"{}": 42}}}}"#,
+                                uuid,
+                                temp_id));
+    transport.push_response(HttpResponse {
+        status: 400,
+        headers: vec![],
+        body: r#"{"error": "Bad request"}"#.into(),
+    });
+
+    let mut result = CommandResponse::default();
+    assert!(m.flush_into(&mut result).is_err());
+    assert!(result.is_ok(&uuid));
+    assert_eq!(result.id_of(&temp_id), Some(42));
+}
+
+#[test]
+fn created_objects_are_used_by_temp_id() {
+    let fake = FakeTodoist::new();
+    let inbox = fake.add_project("Inbox", 1);
+
+    let mut todoist = Todoist::with_transport("offline", fake.clone());
+    let mut m = todoist.manager();
+    let (project, _) = m.add_project("Garden");
+    let (item, _) = m.add_item(project, "mow the lawn");
+    let (note, _) = m.add_note(item, "the blades need sharpening");
+    let result = m.flush().unwrap();
+    assert!(result.failures().is_empty());
+
+    let (project, item, note) =
+        (result.id_of(&project).unwrap(), result.id_of(&item).unwrap(), result.id_of(&note).unwrap());
+    assert_eq!(fake.project(project).unwrap().name, "Garden");
+    assert_eq!(fake.item(item).unwrap().project_id, project);
+    assert_eq!(fake.notes(item).iter().map(|n| n.id).collect::<Vec<_>>(), vec![note]);
+
+    let mut m = todoist.manager();
+    m.rename_project(project, "Backyard");
+    m.reorder_projects(&[(project, 1, 1), (inbox, 2, 1)]);
+    m.move_item(item, project, inbox);
+    m.reorder_items(&[(item, 5, 2)]);
+    m.complete_item(item);
+    m.uncomplete_item(item);
+    let result = m.flush().unwrap();
+    assert!(result.failures().is_empty());
+
+    let (garden, mow) = (fake.project(project).unwrap(), fake.item(item).unwrap());
+    assert_eq!((garden.name.as_str(), garden.item_order), ("Backyard", 1));
+    assert_eq!(fake.project(inbox).unwrap().item_order, 2);
+    assert_eq!((mow.project_id, mow.item_order, mow.indent, mow.checked), (inbox, 5, 2, 0));
+
+    let mut m = todoist.manager();
+    m.delete_item(item);
+    m.flush().unwrap();
+    assert_eq!(fake.item(item).unwrap().is_deleted, 1);
+}
+
+#[test]
+fn crud_wire_format() {
+    let mut todoist = Todoist::with_transport("offline", MemoryTransport::new());
+    let mut m = todoist.manager();
+    let (project, _) = m.add_project("Garden");
+    m.add_item(project, "mow the lawn");
+    m.move_item(7, 1, 2);
+    m.reorder_items(&[(7, 3, 2)]);
+
+    let args: Vec<serde_json::Value> = m.commands().iter().map(|c| to_value(c).unwrap()["args"].clone()).collect();
+    assert_eq!(args[0], json(r#"{"name": "Garden"}"#));
+    assert_eq!(args[1],
+               json(&format!(r#"{{"content": "mow the lawn", "project_id": "{}"}}"#, project)));
+    assert_eq!(args[2], json(r#"{"project_items": {"1": [7]}, "to_project": 2}"#));
+    assert_eq!(args[3], json(r#"{"ids_to_orders_indents": {"7": [3, 2]}}"#));
+}
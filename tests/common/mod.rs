@@ -0,0 +1,41 @@
+//! Builders shared by the tests. Not every test file uses all of them.
+#![allow(dead_code)]
+
+use nextaction::{Project, Item};
+
+/// A top level project, placed by its id.
+pub fn project(id: usize, name: &str) -> Project {
+    Project {
+        id: id,
+        name: name.into(),
+        item_order: id,
+        indent: 1,
+        ..Default::default()
+    }
+}
+
+/// A task of project 1, placed by its id.
+pub fn item(id: usize, content: &str, indent: usize, labels: Vec<usize>) -> Item {
+    Item {
+        id: id,
+        project_id: 1,
+        content: content.into(),
+        item_order: id,
+        indent: indent,
+        labels: labels,
+        ..Default::default()
+    }
+}
+
+/// A top level task of `project_id`, ticked off or not.
+pub fn task(id: usize, project_id: usize, checked: bool) -> Item {
+    Item {
+        id: id,
+        project_id: project_id,
+        content: format!("task {}", id),
+        item_order: id,
+        indent: 1,
+        checked: checked as usize,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,82 @@
+extern crate nextaction;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nextaction::{Account, NextAction, Todoist, FakeTodoist, StateStore, Transport, Result};
+use nextaction::daemon::{self, WebhookSettings};
+use nextaction::transport::{HttpRequest, HttpResponse};
+
+fn state_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("nextaction-daemon-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn client(fake: &FakeTodoist, name: &str, nextaction_name: &str, root: &PathBuf) -> NextAction {
+    let mut account = Account::new(name, "secret");
+    account.nextaction_name = nextaction_name.into();
+    account.state_dir = Some(root.join(name));
+    account.next_action_with(Todoist::with_transport("secret", fake.clone()))
+}
+
+#[test]
+fn accounts_keep_to_themselves() {
+    let root = state_dir("accounts");
+    let (alice, bob) = (FakeTodoist::new(), FakeTodoist::new());
+    let item = alice.add_item(alice.add_project("Book:", 1), "outline", 1);
+    bob.add_item(bob.add_project("Garden:", 1), "mow", 1);
+
+    client(&alice, "alice", "next", &root).step().unwrap();
+    client(&bob, "bob", "now", &root).step().unwrap();
+
+    assert_eq!(alice.label_names(item), vec!["next"]);
+    let alice_state = StateStore::new(root.join("alice")).load().unwrap();
+    let bob_state = StateStore::new(root.join("bob")).load().unwrap();
+    assert_eq!(alice_state.nextaction_name, "next");
+    assert_eq!(bob_state.nextaction_name, "now");
+    assert_eq!(alice_state.items.len(), 1);
+    assert_eq!(alice_state.items[0].content, "outline");
+
+    // a restarted account picks up its own state
+    client(&alice, "alice", "next", &root).step().unwrap();
+    assert_ne!(alice.requests().last().unwrap().param("sync_token"), Some("*"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+struct Panicking;
+
+impl Transport for Panicking {
+    fn post(&self, _: &HttpRequest) -> Result<HttpResponse> {
+        panic!("bug in the transport")
+    }
+}
+
+#[test]
+fn a_panicking_round_is_an_error() {
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", Panicking));
+    let err = na.loopit(0).unwrap_err();
+    assert!(err.to_string().contains("panicked"));
+}
+
+#[test]
+fn webhook_addresses_are_not_shared() {
+    let webhook = |addr: &str| {
+        Some(WebhookSettings {
+            addr: addr.into(),
+            secret: "client secret".into(),
+            debounce: Duration::new(2, 0),
+            fallback: Duration::new(600, 0),
+        })
+    };
+    let mut accounts = vec![Account::new("alice", "a"), Account::new("bob", "b"), Account::new("carol", "c")];
+    accounts[0].webhook = webhook("0.0.0.0:8000");
+    accounts[1].webhook = webhook("0.0.0.0:8001");
+    assert!(daemon::check(&accounts).is_ok());
+
+    accounts[2].webhook = webhook("0.0.0.0:8000");
+    let err = daemon::check(&accounts).unwrap_err().to_string();
+    assert!(err.contains("'alice' and 'carol'"));
+}
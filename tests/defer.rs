@@ -0,0 +1,163 @@
+extern crate nextaction;
+use std::env;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use nextaction::{NextAction, Todoist, FakeTodoist, TaskTree, Item, Rules, Reason, Day, TzInfo, User};
+use nextaction::defer::{start_date, utc_offset};
+
+mod common;
+use common::{project, item};
+
+const NEXTACTION: usize = 1;
+const SOMEDAY: usize = 2;
+
+fn day(s: &str) -> Day {
+    s.parse().unwrap()
+}
+
+#[test]
+fn days() {
+    assert_eq!(day("2026-11-01"),
+               Day {
+                   year: 2026,
+                   month: 11,
+                   day: 1,
+               });
+    assert_eq!(day("2024-02-29").to_string(), "2024-02-29");
+    assert!("2023-02-29".parse::<Day>().is_err());
+    assert!("2026-13-01".parse::<Day>().is_err());
+    assert!("1 Nov".parse::<Day>().is_err());
+
+    assert_eq!(Day::at(0, 0), day("1970-01-01"));
+    assert_eq!(Day::at(1_700_000_000, 0), day("2023-11-14"));
+    // 22:13 UTC is already the next day in Auckland, still the same in Santiago
+    assert_eq!(Day::at(1_700_000_000, 13 * 60 * 60), day("2023-11-15"));
+    assert_eq!(Day::at(1_700_000_000, -3 * 60 * 60), day("2023-11-14"));
+    assert_eq!(Day::at(0, -60), day("1969-12-31"));
+}
+
+#[test]
+fn offsets_and_tokens() {
+    let tz = |hours: i64, minutes: i64, gmt: &str| {
+        TzInfo {
+            hours: hours,
+            minutes: minutes,
+            gmt_string: gmt.into(),
+            ..Default::default()
+        }
+    };
+    assert_eq!(utc_offset(&tz(8, 0, "+08:00")), 8 * 60 * 60);
+    assert_eq!(utc_offset(&tz(-3, 30, "-03:30")), -(3 * 60 + 30) * 60);
+    assert_eq!(utc_offset(&tz(0, 30, "-00:30")), -30 * 60);
+
+    assert_eq!(start_date("Renew passport start:2026-11-01"), Some(day("2026-11-01")));
+    assert_eq!(start_date("start:soon start:2026-11-01"), Some(day("2026-11-01")));
+    assert_eq!(start_date("Renew passport"), None);
+}
+
+fn changes(name: &str, items: &[Item], rules: &Rules) -> Vec<(usize, Reason)> {
+    let tree = TaskTree::build(&[project(1, name)], items).unwrap();
+    tree.label_changes(rules).iter().map(|c| (c.item_id, c.reason)).collect()
+}
+
+#[test]
+fn deferred_tasks_wait_for_their_day() {
+    let items = [item(10, "renew passport start:2026-11-01", 1, vec![NEXTACTION]), item(11, "pack", 1, vec![])];
+    let mut rules = Rules::new(NEXTACTION, SOMEDAY);
+    // without a day start dates don't matter
+    assert_eq!(changes("Trip:", &items, &rules), vec![]);
+
+    rules.today = Some(day("2026-10-31"));
+    // still open, so it holds up the rest of a sequential project
+    assert_eq!(changes("Trip:", &items, &rules), vec![(10, Reason::Deferred)]);
+    assert_eq!(changes("Trip-", &items, &rules), vec![(10, Reason::Deferred), (11, Reason::NextAction)]);
+
+    rules.today = Some(day("2026-11-01"));
+    assert_eq!(changes("Trip:", &items, &rules), vec![]);
+}
+
+#[test]
+fn deferral_covers_subtasks_and_notes() {
+    let items = [item(10, "errands-", 1, vec![]),
+                 item(11, "bank", 2, vec![NEXTACTION]),
+                 item(12, "post office", 2, vec![NEXTACTION])];
+    let mut rules = Rules::new(NEXTACTION, SOMEDAY);
+    rules.today = Some(day("2026-10-31"));
+    rules.start_dates.insert(10, day("2026-11-02"));
+    assert_eq!(changes("Week-", &items, &rules),
+               vec![(11, Reason::Deferred), (12, Reason::Deferred)]);
+    // one in the content goes first
+    let mut items = items.to_vec();
+    items[0].content = "start:2026-10-30 errands-".into();
+    assert_eq!(changes("Week-", &items, &rules), vec![]);
+}
+
+#[test]
+fn start_dates_follow_the_user_time_zone() {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // somewhere it is already this day, somewhere else it is still the day before
+    let ahead = Day::at(now, 14 * 60 * 60);
+
+    let round = |hours: i64, gmt: &str| {
+        let fake = FakeTodoist::new();
+        fake.set_user(User {
+            tz_info: Some(TzInfo {
+                hours: hours,
+                gmt_string: gmt.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let project = fake.add_project("Week-", 1);
+        let task = fake.add_item(project, "call Ann", 1);
+        fake.add_note(task, &format!("not before start:{}", ahead));
+        let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+        na.step().unwrap();
+        fake.label_names(task)
+    };
+    assert_eq!(round(14, "+14:00"), vec!["NextAction"]);
+    assert!(round(-12, "-12:00").is_empty());
+}
+
+#[test]
+fn time_zone_changes_are_picked_up() {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let ahead = Day::at(now, 14 * 60 * 60);
+    let user = |hours: i64, gmt: &str| {
+        User {
+            tz_info: Some(TzInfo {
+                hours: hours,
+                gmt_string: gmt.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    };
+
+    let dir = env::temp_dir().join(format!("nextaction-defer-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let fake = FakeTodoist::new();
+    fake.set_user(user(-12, "-12:00"));
+    let project = fake.add_project("Week-", 1);
+    let task = fake.add_item(project, &format!("call Ann start:{}", ahead), 1);
+    let client = || {
+        let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+        na.set_state_dir(&dir);
+        na
+    };
+    let user_reads = || fake.requests().iter().filter(|r| r.param("resource_types") == Some(r#"["user"]"#)).count();
+    client().step().unwrap();
+    assert!(fake.label_names(task).is_empty());
+
+    // nothing else changed, the time zone is read again once the day is new to the client
+    fake.set_user(user(14, "+14:00"));
+    let mut na = client();
+    na.step().unwrap();
+    assert_eq!(fake.label_names(task), vec!["NextAction"]);
+    assert_eq!(user_reads(), 1);
+    // and not again that day
+    na.step().unwrap();
+    assert_eq!(user_reads(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
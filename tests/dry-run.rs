@@ -0,0 +1,52 @@
+extern crate nextaction;
+use nextaction::{NextAction, Todoist, FakeTodoist, Reason};
+
+#[test]
+fn plan_is_not_sent() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Book:", 1);
+    let first = fake.add_item(project, "outline", 1);
+    let second = fake.add_item(project, "draft", 1);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.dry_run = true;
+    let plan = na.step().unwrap();
+
+    // not even the missing labels are created
+    assert!(fake.commands().is_empty());
+    assert_eq!(plan.changes.len(), 1);
+    assert_eq!(plan.changes[0].item_id, first);
+    assert_eq!(plan.changes[0].content, "outline");
+    assert!(plan.changes[0].labels_before.is_empty());
+    assert_eq!(plan.changes[0].reason, Reason::NextAction);
+    assert_eq!(plan.label_names[&plan.changes[0].labels_after[0]], "NextAction");
+    assert!(format!("{}", plan).contains("outline"));
+    // the rest of a sequential project waits its turn
+    assert!(plan.changes.iter().all(|c| c.item_id != second));
+    assert!(fake.label_names(first).is_empty());
+}
+
+#[test]
+fn plan_explains_removals() {
+    let fake = FakeTodoist::new();
+    let nextaction = fake.add_label("NextAction");
+    fake.add_label("Someday");
+    let project = fake.add_project("Book:", 1);
+    let first = fake.add_item(project, "outline", 1);
+    let second = fake.add_item(project, "draft", 1);
+    fake.set_labels(first, vec![nextaction]);
+    fake.set_labels(second, vec![nextaction]);
+    fake.complete_item(first);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    let plan = na.step().unwrap();
+
+    let reasons: Vec<(usize, Reason)> = plan.changes.iter().map(|c| (c.item_id, c.reason)).collect();
+    assert_eq!(reasons, vec![(first, Reason::Completed)]);
+
+    let third = fake.add_item(project, "edit", 1);
+    fake.set_labels(third, vec![nextaction]);
+    let plan = na.step().unwrap();
+    let reasons: Vec<(usize, Reason)> = plan.changes.iter().map(|c| (c.item_id, c.reason)).collect();
+    assert_eq!(reasons, vec![(third, Reason::Blocked)]);
+}
@@ -0,0 +1,237 @@
+extern crate nextaction;
+use nextaction::{NextAction, Todoist, FakeTodoist, TaskTree, Section, Item, Rules, LabelChange, Reason, Completion,
+                 Grouping};
+
+mod common;
+use common::{project, item};
+
+const NEXTACTION: usize = 1;
+const SOMEDAY: usize = 2;
+
+fn changes(items: &[Item]) -> Vec<LabelChange> {
+    let tree = TaskTree::build(&[project(1, "Trip:")], items).unwrap();
+    tree.label_changes(&Rules::new(NEXTACTION, SOMEDAY))
+}
+
+#[test]
+fn sequential_tags_first_open_item() {
+    let changes = changes(&[item(10, "book flights", 1, vec![]), item(11, "pack", 1, vec![])]);
+    assert_eq!(changes,
+               vec![LabelChange {
+                        item_id: 10,
+                        add: vec![NEXTACTION],
+                        remove: vec![],
+                        reason: Reason::NextAction,
+                    }]);
+}
+
+#[test]
+fn blocked_item_loses_label() {
+    let changes = changes(&[item(10, "book flights", 1, vec![]), item(11, "pack", 1, vec![NEXTACTION, 7])]);
+    assert_eq!(changes[1],
+               LabelChange {
+                   item_id: 11,
+                   add: vec![],
+                   remove: vec![NEXTACTION],
+                   reason: Reason::Blocked,
+               });
+    assert_eq!(changes[1].apply(&[NEXTACTION, 7]), vec![7]);
+}
+
+#[test]
+fn nested_parallel_group() {
+    let changes = changes(&[item(10, "errands-", 1, vec![]),
+                            item(11, "bank", 2, vec![]),
+                            item(12, "post office", 2, vec![SOMEDAY]),
+                            item(13, "pack", 1, vec![])]);
+    let tagged: Vec<usize> = changes.iter().filter(|c| c.add == vec![NEXTACTION]).map(|c| c.item_id).collect();
+    assert_eq!(tagged, vec![11]);
+}
+
+#[test]
+fn unsorted_input() {
+    let sorted = changes(&[item(10, "book flights", 1, vec![]), item(11, "pack", 1, vec![])]);
+    let unsorted = changes(&[item(11, "pack", 1, vec![]), item(10, "book flights", 1, vec![])]);
+    assert_eq!(sorted, unsorted);
+}
+
+fn section(id: usize, name: &str) -> Section {
+    Section {
+        id: id,
+        name: name.into(),
+        project_id: 1,
+        section_order: id,
+        ..Default::default()
+    }
+}
+
+fn tagged_in_sections(name: &str, items: &[Item]) -> Vec<usize> {
+    let sections = [section(100, "Before:"), section(200, "During-"), section(300, "After"), section(400, "Later")];
+    let tree = TaskTree::build_with_sections(&[project(1, name)], &sections, items).unwrap();
+    tree.label_changes(&Rules::new(NEXTACTION, SOMEDAY))
+        .iter()
+        .filter(|c| c.add == vec![NEXTACTION])
+        .map(|c| c.item_id)
+        .collect()
+}
+
+fn sectioned(id: usize, content: &str, section_id: usize) -> Item {
+    Item { section_id: Some(section_id), ..item(id, content, 1, vec![]) }
+}
+
+#[test]
+fn sections_are_groups_of_their_own() {
+    let items = [sectioned(10, "book flights", 100),
+                 sectioned(11, "pack", 100),
+                 sectioned(20, "museum", 200),
+                 sectioned(21, "beach", 200),
+                 sectioned(30, "laundry", 300)];
+    // the project itself isn't marked, each section shows its own next actions
+    assert_eq!(tagged_in_sections("Trip", &items), vec![10, 20, 21]);
+}
+
+#[test]
+fn open_section_blocks_a_sequential_project() {
+    let mut items = vec![sectioned(10, "book flights", 100), sectioned(20, "museum", 200)];
+    assert_eq!(tagged_in_sections("Trip:", &items), vec![10]);
+
+    items[0].checked = 1;
+    assert_eq!(tagged_in_sections("Trip:", &items), vec![20]);
+}
+
+#[test]
+fn plain_sections_follow_their_project() {
+    let mut items = vec![sectioned(30, "laundry", 300), sectioned(31, "unpack", 300), sectioned(40, "photos", 400)];
+    // without a marker of their own sections are just steps of the project
+    assert_eq!(tagged_in_sections("Trip:", &items), vec![30]);
+    assert_eq!(tagged_in_sections("Trip-", &items), vec![30, 31, 40]);
+
+    items[0].checked = 1;
+    items[1].checked = 1;
+    assert_eq!(tagged_in_sections("Trip:", &items), vec![40]);
+}
+
+fn done(id: usize, content: &str, indent: usize) -> Item {
+    Item { checked: 1, ..item(id, content, indent, vec![]) }
+}
+
+fn completions(items: &[Item], rules: &Rules) -> Vec<usize> {
+    let tree = TaskTree::build(&[project(1, "Trip:")], items).unwrap();
+    tree.completions(rules).iter().map(|c| c.item_id).collect()
+}
+
+#[test]
+fn parents_of_done_subtasks_are_closed_on_request() {
+    let items = [item(10, "errands-", 1, vec![]), done(11, "bank", 2), done(12, "post office", 2)];
+    let mut rules = Rules::new(NEXTACTION, SOMEDAY);
+    assert!(completions(&items, &rules).is_empty());
+
+    rules.complete_sequential = true;
+    assert!(completions(&items, &rules).is_empty());
+    rules.complete_parallel = true;
+    let tree = TaskTree::build(&[project(1, "Trip:")], &items).unwrap();
+    assert_eq!(tree.completions(&rules),
+               vec![Completion {
+                        item_id: 10,
+                        grouping: Grouping::Parallel,
+                    }]);
+
+    // an open subtask, a recurring task or a plain task stay open
+    let mut open = items.to_vec();
+    open[2].checked = 0;
+    assert!(completions(&open, &rules).is_empty());
+    let mut recurring = items.to_vec();
+    recurring[0].date_string = Some("every monday".into());
+    assert!(completions(&recurring, &rules).is_empty());
+    let mut plain = items.to_vec();
+    plain[0].content = "errands".into();
+    assert!(completions(&plain, &rules).is_empty());
+}
+
+#[test]
+fn closing_cascades_and_unblocks_in_the_same_round() {
+    let items = [item(10, "prepare:", 1, vec![]),
+                 item(11, "documents-", 2, vec![]),
+                 done(12, "passport", 3),
+                 done(13, "visa", 3),
+                 done(14, "book flights", 2),
+                 item(20, "pack", 1, vec![])];
+    let mut rules = Rules::new(NEXTACTION, SOMEDAY);
+    rules.complete_parallel = true;
+    rules.complete_sequential = true;
+    assert_eq!(completions(&items, &rules), vec![11, 10]);
+
+    let tree = TaskTree::build(&[project(1, "Trip:")], &items).unwrap();
+    let tagged: Vec<usize> = tree.label_changes(&rules)
+        .iter()
+        .filter(|c| c.add == vec![NEXTACTION])
+        .map(|c| c.item_id)
+        .collect();
+    assert_eq!(tagged, vec![20]);
+}
+
+#[test]
+fn closed_parents_reach_todoist_and_the_plan() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Week-", 1);
+    let errands = fake.add_item(project, "errands:", 1);
+    let bank = fake.add_item(project, "bank", 2);
+    fake.complete_item(bank);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.complete_sequential = true;
+    let plan = na.step().unwrap();
+
+    assert_eq!(plan.completions.len(), 1);
+    assert_eq!(plan.completions[0].content, "errands:");
+    assert!(format!("{}", plan).contains("Close"));
+    assert_eq!(fake.item(errands).unwrap().checked, 1);
+}
+
+#[test]
+fn someday_holds_the_whole_subtree() {
+    let items = [item(10, "errands-", 1, vec![SOMEDAY]),
+                 item(11, "bank", 2, vec![NEXTACTION]),
+                 item(12, "shops:", 2, vec![]),
+                 item(13, "bakery", 3, vec![NEXTACTION]),
+                 item(20, "pack", 1, vec![])];
+    let tree = TaskTree::build(&[project(1, "Trip-")], &items).unwrap();
+    let changes = tree.label_changes(&Rules::new(NEXTACTION, SOMEDAY));
+    assert_eq!(changes,
+               vec![LabelChange {
+                        item_id: 11,
+                        add: vec![],
+                        remove: vec![NEXTACTION],
+                        reason: Reason::Someday,
+                    },
+                    LabelChange {
+                        item_id: 13,
+                        add: vec![],
+                        remove: vec![NEXTACTION],
+                        reason: Reason::Someday,
+                    },
+                    LabelChange {
+                        item_id: 20,
+                        add: vec![NEXTACTION],
+                        remove: vec![],
+                        reason: Reason::NextAction,
+                    }]);
+}
+
+#[test]
+fn projects_on_hold() {
+    let items = [item(10, "book flights", 1, vec![NEXTACTION]), item(11, "pack", 1, vec![])];
+    let changes = |name: &str, rules: &Rules| {
+        let tree = TaskTree::build(&[project(1, name)], &items).unwrap();
+        tree.label_changes(rules).iter().map(|c| (c.item_id, c.reason.clone())).collect::<Vec<_>>()
+    };
+    let mut rules = Rules::new(NEXTACTION, SOMEDAY);
+    assert!(changes("Trip:", &rules).is_empty());
+
+    rules.on_hold_markers = vec!["tag:[hold]".parse().unwrap(), "suffix:~".parse().unwrap()];
+    assert_eq!(changes("Trip: [hold]", &rules), vec![(10, Reason::Someday)]);
+    // a linked name is matched by its text, as with the grouping markers
+    assert_eq!(changes("[Trip~](https://example.com/trip)", &rules), vec![(10, Reason::Someday)]);
+    rules.on_hold_projects = vec!["trip:".into()];
+    assert_eq!(changes("Trip:", &rules), vec![(10, Reason::Someday)]);
+}
@@ -0,0 +1,120 @@
+extern crate nextaction;
+use nextaction::{NextAction, Todoist, FakeTodoist};
+
+fn client(fake: &FakeTodoist) -> NextAction {
+    NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()))
+}
+
+#[test]
+fn creates_missing_labels() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Inbox-", 1);
+    let item = fake.add_item(project, "plain task", 1);
+
+    client(&fake).step().unwrap();
+
+    assert_eq!(fake.label_names(item), vec!["NextAction".to_string()]);
+    let created: Vec<String> = fake.commands()
+        .iter()
+        .filter(|c| c["type"] == "label_add")
+        .map(|c| c["args"]["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(created, vec!["NextAction".to_string(), "Someday".to_string()]);
+}
+
+#[test]
+fn sequential_project_moves_on() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Book:", 1);
+    let first = fake.add_item(project, "outline", 1);
+    let second = fake.add_item(project, "draft", 1);
+    let third = fake.add_item(project, "edit", 1);
+
+    let mut na = client(&fake);
+    na.step().unwrap();
+    assert_eq!(fake.label_names(first), vec!["NextAction".to_string()]);
+    assert!(fake.label_names(second).is_empty());
+    assert!(fake.label_names(third).is_empty());
+
+    fake.complete_item(first);
+    na.step().unwrap();
+    assert!(fake.label_names(first).is_empty());
+    assert_eq!(fake.label_names(second), vec!["NextAction".to_string()]);
+    assert!(fake.label_names(third).is_empty());
+}
+
+#[test]
+fn parallel_subtasks() {
+    let fake = FakeTodoist::new();
+    let someday = fake.add_label("Someday");
+    let project = fake.add_project("Home", 1);
+    let parent = fake.add_item(project, "chores-", 1);
+    let a = fake.add_item(project, "dishes", 2);
+    let b = fake.add_item(project, "laundry", 2);
+    let c = fake.add_item(project, "paint the fence", 2);
+    fake.complete_item(a);
+    fake.set_labels(c, vec![someday]);
+
+    client(&fake).step().unwrap();
+    assert!(fake.label_names(parent).is_empty());
+    assert!(fake.label_names(a).is_empty());
+    assert_eq!(fake.label_names(b), vec!["NextAction".to_string()]);
+    assert_eq!(fake.label_names(c), vec!["Someday".to_string()]);
+}
+
+#[test]
+fn seeded_from_dump() {
+    let fake = FakeTodoist::from_json(include_str!("../format.json")).unwrap();
+    let mut na = client(&fake);
+    na.nextaction_name = "next_action".into();
+    na.someday_name = "someday".into();
+    na.step().unwrap();
+
+    // both labels already exist in the dump
+    assert!(fake.commands().iter().all(|c| c["type"] == "item_update"));
+}
+
+#[test]
+fn incremental_sync() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Book:", 1);
+    let first = fake.add_item(project, "outline", 1);
+    let second = fake.add_item(project, "draft", 1);
+    let other = fake.add_project("Garden-", 1);
+    let weeds = fake.add_item(other, "weeds", 1);
+
+    let mut na = client(&fake);
+    na.step().unwrap();
+    fake.complete_item(first);
+    fake.archive_project(other);
+    na.step().unwrap();
+
+    let tokens: Vec<String> = fake.requests()
+        .iter()
+        .filter_map(|r| r.param("sync_token").map(|t| t.to_string()))
+        .collect();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0], "*");
+    assert!(tokens[1] != "*");
+
+    assert_eq!(fake.label_names(second), vec!["NextAction".to_string()]);
+    // the archived project's item left the model with its project, so it keeps the label it had
+    assert_eq!(fake.label_names(weeds), vec!["NextAction".to_string()]);
+}
+
+#[test]
+fn full_sync_every_round() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Book:", 1);
+    fake.add_item(project, "outline", 1);
+
+    let mut na = client(&fake);
+    na.incremental = false;
+    na.step().unwrap();
+    na.step().unwrap();
+
+    assert!(fake.requests()
+        .iter()
+        .filter_map(|r| r.param("sync_token"))
+        .all(|t| t == "*"));
+}
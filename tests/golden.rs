@@ -0,0 +1,105 @@
+//! Replays `format.json` through `build_tree` and `traversal` and compares the
+//! `item_update` commands against the snapshots in `tests/golden/`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the snapshots after an intended change.
+
+extern crate nextaction;
+extern crate serde_json;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use serde_json::from_str;
+
+use nextaction::{NextAction, Todoist, TodoistResponse, MemoryTransport, Rules, CommandKind, ObjectId};
+
+fn dump() -> TodoistResponse {
+    from_str(include_str!("../format.json")).unwrap()
+}
+
+fn nextaction() -> NextAction {
+    let mut na = NextAction::with_todoist(Todoist::with_transport("offline", MemoryTransport::new()));
+    na.nextaction_name = "next_action".into();
+    na.someday_name = "someday".into();
+    na
+}
+
+/// The `item_update` commands one round would emit for `response`.
+fn plan(response: &TodoistResponse) -> Vec<(usize, Vec<usize>)> {
+    let mut na = nextaction();
+    na.merge(response);
+    na.build_tree().unwrap();
+
+    let mut todoist = Todoist::with_transport("offline", MemoryTransport::new());
+    let mut m = todoist.manager();
+    na.tree().queue(&mut m, &Rules::new(na.nextaction_id().unwrap(), na.someday_id().unwrap()));
+
+    let real = |id: &ObjectId| match *id {
+        ObjectId::Id(id) => id,
+        ObjectId::Temp(_) => panic!("no temp ids expected"),
+    };
+    let mut updates: Vec<(usize, Vec<usize>)> = m.commands()
+        .iter()
+        .filter_map(|c| match c.kind {
+            CommandKind::ItemUpdate { ref id, labels: Some(ref labels) } => {
+                Some((real(id), labels.iter().map(&real).collect()))
+            }
+            _ => None,
+        })
+        .collect();
+    updates.sort();
+    updates
+}
+
+fn render(response: &TodoistResponse, updates: &[(usize, Vec<usize>)]) -> String {
+    let items = response.items.as_ref().unwrap();
+    let mut out = String::new();
+    for &(id, ref labels) in updates {
+        let content = items.iter().find(|i| i.id == id).map(|i| i.content.as_str()).unwrap_or("");
+        out.push_str(&format!("{} {:?} # {}\n", id, labels, content));
+    }
+    out
+}
+
+fn check(name: &str, actual: &str) {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests/golden");
+    path.push(format!("{}.snap", name));
+
+    if env::var("UPDATE_GOLDEN").is_ok() {
+        File::create(&path).unwrap().write_all(actual.as_bytes()).unwrap();
+        return;
+    }
+
+    let mut expected = String::new();
+    File::open(&path)
+        .expect("snapshot missing, run with UPDATE_GOLDEN=1")
+        .read_to_string(&mut expected)
+        .unwrap();
+    assert!(expected == actual,
+            "snapshot '{}' differs:\n--- expected\n{}\n--- actual\n{}",
+            name,
+            expected,
+            actual);
+}
+
+#[test]
+fn first_round() {
+    let response = dump();
+    let updates = plan(&response);
+    check("first_round", &render(&response, &updates));
+}
+
+#[test]
+fn second_round_is_quiet() {
+    // feed the first round back, as Todoist would after the flush
+    let mut response = dump();
+    for (id, labels) in plan(&response) {
+        let item = response.items.as_mut().unwrap().iter_mut().find(|i| i.id == id).unwrap();
+        item.labels = labels;
+    }
+    let updates = plan(&response);
+    check("second_round", &render(&response, &updates));
+}
@@ -0,0 +1,119 @@
+extern crate nextaction;
+use nextaction::{NextAction, Todoist, FakeTodoist, TaskTree, Project, Item, Rules, Markers, Marker, Grouping};
+
+mod common;
+use common::{project, item};
+
+fn markers(parallel: &[&str], sequential: &[&str]) -> Markers {
+    Markers {
+        parallel: parallel.iter().map(|s| s.parse().unwrap()).collect(),
+        sequential: sequential.iter().map(|s| s.parse().unwrap()).collect(),
+    }
+}
+
+#[test]
+fn default_suffixes() {
+    let markers = Markers::default();
+    assert_eq!(markers.grouping("Trip:"), Some(Grouping::Sequential));
+    assert_eq!(markers.grouping("Errands-"), Some(Grouping::Parallel));
+    assert_eq!(markers.grouping("Errands - "), Some(Grouping::Parallel));
+    assert_eq!(markers.grouping("Trip"), None);
+}
+
+#[test]
+fn links_are_ignored() {
+    let markers = Markers::default();
+    assert_eq!(markers.grouping("Read [the guide](https://example.com/a-b) :"),
+               Some(Grouping::Sequential));
+    assert_eq!(markers.grouping("[Sign up](https://example.com/x-)"), None);
+    assert_eq!(markers.grouping("[seq] see [docs](https://example.com)-"),
+               Some(Grouping::Parallel));
+}
+
+#[test]
+fn custom_markers() {
+    let markers = markers(&["prefix:⏩", "tag:[par]"], &["suffix:🔁", "tag:[seq]"]);
+    assert_eq!(markers.grouping("⏩ Errands"), Some(Grouping::Parallel));
+    assert_eq!(markers.grouping("Errands [par] this week"), Some(Grouping::Parallel));
+    assert_eq!(markers.grouping("Trip 🔁"), Some(Grouping::Sequential));
+    assert_eq!(markers.grouping("[seq] Trip"), Some(Grouping::Sequential));
+    assert_eq!(markers.grouping("Agenda:"), None);
+    assert_eq!(markers.grouping("Trip[seq]"), None);
+}
+
+#[test]
+fn marker_specs() {
+    assert_eq!("suffix::".parse::<Marker>().unwrap(), Marker::Suffix(":".into()));
+    assert_eq!("tag:[seq]".parse::<Marker>().unwrap(), Marker::Tag("[seq]".into()));
+    assert!("suffix:".parse::<Marker>().is_err());
+    assert!("infix:-".parse::<Marker>().is_err());
+}
+
+fn tagged(project: Project, items: &[Item], rules: &Rules) -> Vec<usize> {
+    let tree = TaskTree::build(&[project], items).unwrap();
+    tree.label_changes(rules).iter().filter(|c| c.add == vec![1]).map(|c| c.item_id).collect()
+}
+
+#[test]
+fn engine_follows_the_markers() {
+    let items = [item(10, "call Ann", 1, vec![]), item(11, "call Bob", 1, vec![])];
+
+    let mut rules = Rules::new(1, 2);
+    assert_eq!(tagged(project(1, "Agenda:"), &items, &rules), vec![10]);
+    // with bracket tags only, "Agenda:" is a plain project and left alone
+    rules.markers = markers(&["tag:[par]"], &["tag:[seq]"]);
+    assert!(tagged(project(1, "Agenda:"), &items, &rules).is_empty());
+}
+
+#[test]
+fn labels_give_the_grouping() {
+    const PARALLEL: usize = 3;
+    const SEQUENTIAL: usize = 4;
+    let mut rules = Rules::new(1, 2);
+    rules.parallel_id = Some(PARALLEL);
+    rules.sequential_id = Some(SEQUENTIAL);
+
+    let items = |labels: Vec<usize>, name: &str| {
+        vec![item(10, name, 1, labels), item(11, "bank", 2, vec![]), item(12, "post office", 2, vec![])]
+    };
+    assert_eq!(tagged(project(1, "Week-"), &items(vec![SEQUENTIAL], "errands"), &rules), vec![11]);
+    assert_eq!(tagged(project(1, "Week-"), &items(vec![PARALLEL], "errands"), &rules), vec![11, 12]);
+    // the name goes first
+    assert_eq!(tagged(project(1, "Week-"), &items(vec![PARALLEL], "errands:"), &rules), vec![11]);
+    // without a mode, the task itself is the next action
+    assert_eq!(tagged(project(1, "Week-"), &items(vec![], "errands"), &rules), vec![10]);
+}
+
+#[test]
+fn project_modes() {
+    let items = [item(10, "call Ann", 1, vec![]), item(11, "call Bob", 1, vec![])];
+    let mut rules = Rules::new(1, 2);
+    assert!(tagged(project(1, "Agenda"), &items, &rules).is_empty());
+
+    rules.project_modes.insert("agenda".into(), Grouping::Parallel);
+    assert_eq!(tagged(project(1, "Agenda"), &items, &rules), vec![10, 11]);
+    // the id goes before the name, the name marker before both
+    rules.project_modes.insert("1".into(), Grouping::Sequential);
+    assert_eq!(tagged(project(1, "Agenda"), &items, &rules), vec![10]);
+    assert_eq!(tagged(project(1, "Agenda-"), &items, &rules), vec![10, 11]);
+}
+
+#[test]
+fn grouping_labels_are_created() {
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Week-", 1);
+    let errands = fake.add_item(project, "errands", 1);
+    let bank = fake.add_item(project, "bank", 2);
+    let post = fake.add_item(project, "post office", 2);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.sequential_label = Some("sequential".into());
+    na.step().unwrap();
+    let sequential = na.rules().unwrap().sequential_id.unwrap();
+    assert_eq!(na.rules().unwrap().parallel_id, None);
+
+    fake.set_labels(errands, vec![sequential]);
+    na.step().unwrap();
+    assert_eq!(fake.label_names(bank), vec!["NextAction"]);
+    assert!(fake.label_names(post).is_empty());
+}
@@ -0,0 +1,61 @@
+extern crate nextaction;
+extern crate serde_json;
+
+use serde_json::{from_str, to_value};
+use nextaction::{TodoistResponse, Item};
+
+fn dump() -> TodoistResponse {
+    from_str(include_str!("../format.json")).unwrap()
+}
+
+#[test]
+fn every_resource_is_typed() {
+    let response = dump();
+
+    let filters = response.filters.unwrap();
+    assert_eq!(filters.len(), 6);
+    assert_eq!(filters[0].query, "p:Reading & @next_action");
+
+    let reminders = response.reminders.unwrap();
+    assert_eq!(reminders[0].reminder_type, "relative");
+    assert_eq!(reminders[0].item_id, 48840842);
+    assert_eq!(reminders[0].minute_offset, Some(10));
+
+    let notifications = response.live_notifications.unwrap();
+    assert_eq!(notifications[0].notification_type, "karma_level");
+    // fields of one kind of notification only are kept aside
+    assert_eq!(notifications[0].extra["karma_level"], 3);
+
+    assert!(response.collaborators.unwrap().is_empty());
+    assert!(response.collaborator_states.unwrap().is_empty());
+    assert!(response.project_notes.unwrap().is_empty());
+    assert!(response.locations.unwrap().is_empty());
+
+    let notes = response.notes.unwrap();
+    assert_eq!(notes[0].file_attachment.as_ref().unwrap().resource_type, "website");
+
+    let tz = response.user.unwrap().tz_info.unwrap();
+    assert_eq!((tz.hours, tz.minutes, tz.gmt_string.as_str()), (8, 0, "+08:00"));
+}
+
+#[test]
+fn item_dates_and_assignees() {
+    let items = dump().items.unwrap();
+    let due = items.iter().find(|i| i.due_date_utc.is_some()).unwrap();
+    assert!(due.date_string.as_ref().map(|s| !s.is_empty()).unwrap_or(false));
+    assert!(items.iter().any(|i| i.day_order > 0));
+    assert!(items.iter().all(|i| i.responsible_uid.is_none() && i.user_id == Some(5276499)));
+}
+
+#[test]
+fn unknown_fields_survive_a_round_trip() {
+    let item: Item = from_str(r#"{
+        "id": 1, "project_id": 2, "content": "water plants", "indent": 1, "item_order": 1,
+        "date_added": "", "priority": 1, "all_day": false, "labels": [],
+        "is_deleted": 0, "is_archived": 0, "checked": 0, "in_history": 0,
+        "brand_new_field": {"nested": true}
+    }"#)
+        .unwrap();
+    assert_eq!(item.extra["brand_new_field"]["nested"], true);
+    assert_eq!(to_value(&item).unwrap()["brand_new_field"]["nested"], true);
+}
@@ -0,0 +1,148 @@
+extern crate nextaction;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::time::Duration;
+use nextaction::{NextAction, Todoist, FakeTodoist, TaskTree, Item, ReviewRules, ReviewWatch, StateStore};
+
+mod common;
+use common::{project, task};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[test]
+fn reviews_come_round_again() {
+    let rules = ReviewRules::default();
+    let mut watch = ReviewWatch::default();
+    let tree = |items: &[Item]| TaskTree::build(&[project(1, "Trip:")], items).unwrap();
+
+    assert!(watch.update(&tree(&[]), &rules, 0).is_empty());
+    assert!(watch.update(&tree(&[]), &rules, 7 * DAY - 1).is_empty());
+    assert_eq!(watch.update(&tree(&[]), &rules, 7 * DAY), vec![1]);
+    watch.task_added(1, 10);
+    // not again while the task is open
+    assert!(watch.update(&tree(&[task(10, 1, false)]), &rules, 20 * DAY).is_empty());
+
+    // checking it off is a review
+    assert!(watch.update(&tree(&[task(10, 1, true)]), &rules, 21 * DAY).is_empty());
+    assert_eq!(watch.reviews[&1].last_reviewed, 21 * DAY);
+    assert_eq!(watch.reviews[&1].task_id, None);
+    assert_eq!(watch.update(&tree(&[]), &rules, 28 * DAY), vec![1]);
+}
+
+#[test]
+fn project_intervals() {
+    let mut rules = ReviewRules::default();
+    rules.intervals.insert("someday".into(), Duration::new(30 * DAY, 0));
+    rules.intervals.insert("3".into(), Duration::new(0, 0));
+    let mut watch = ReviewWatch::default();
+    let tree = TaskTree::build(&[project(1, "Trip:"), project(2, "Someday"), project(3, "Errands-")], &[])
+        .unwrap();
+
+    watch.update(&tree, &rules, 0);
+    assert_eq!(watch.update(&tree, &rules, 7 * DAY), vec![1]);
+    assert_eq!(watch.update(&tree, &rules, 365 * DAY), vec![1, 2]);
+}
+
+#[test]
+fn review_tasks_are_added() {
+    let fake = FakeTodoist::new();
+    let trip = fake.add_project("Trip:", 1);
+    fake.add_item(trip, "book flights", 1);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.review = Some(ReviewRules {
+        interval: Duration::new(1, 0),
+        ..Default::default()
+    });
+    assert!(na.step().unwrap().reviews.is_empty());
+
+    ::std::thread::sleep(Duration::new(1, 0));
+    let plan = na.step().unwrap();
+    assert_eq!(plan.reviews.iter().map(|r| r.project_id).collect::<Vec<_>>(), vec![trip]);
+    let task = fake.commands()
+        .iter()
+        .filter(|c| c["type"] == "item_add")
+        .map(|c| c["args"]["content"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(task, vec!["Review"]);
+
+    // the open task holds off the next review
+    ::std::thread::sleep(Duration::new(1, 0));
+    assert!(na.step().unwrap().reviews.is_empty());
+}
+
+#[test]
+fn dry_runs_leave_the_watch_alone() {
+    let fake = FakeTodoist::new();
+    fake.add_project("Trip:", 1);
+
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.review = Some(ReviewRules {
+        interval: Duration::new(1, 0),
+        ..Default::default()
+    });
+    na.dry_run = true;
+    na.step().unwrap();
+    ::std::thread::sleep(Duration::new(1, 0));
+    assert!(na.step().unwrap().reviews.is_empty());
+
+    // the first real round is the one that first sees the project
+    na.dry_run = false;
+    assert!(na.step().unwrap().reviews.is_empty());
+    assert!(fake.commands().iter().all(|c| c["type"] != "item_add"));
+}
+
+#[test]
+fn reviews_outlive_the_sync_state() {
+    let dir = env::temp_dir().join(format!("nextaction-reviews-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let fake = FakeTodoist::new();
+    fake.add_project("Trip:", 1);
+    let client = || {
+        let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+        na.review = Some(ReviewRules {
+            interval: Duration::new(1, 0),
+            ..Default::default()
+        });
+        na.set_state_dir(&dir);
+        na
+    };
+    let added = || fake.commands().iter().filter(|c| c["type"] == "item_add").count();
+
+    client().step().unwrap();
+    ::std::thread::sleep(Duration::new(1, 0));
+    assert_eq!(client().step().unwrap().reviews.len(), 1);
+    assert_eq!(added(), 1);
+
+    // a sync state from another version is thrown away, the reviews stay
+    let store = StateStore::new(&dir);
+    File::create(store.path()).unwrap().write_all(br#"{"version": 0}"#).unwrap();
+    ::std::thread::sleep(Duration::new(1, 0));
+    assert!(client().step().unwrap().reviews.is_empty());
+    assert!(store.reviews_path().exists());
+
+    // with the reviews gone too the open task is taken over
+    fs::remove_dir_all(&dir).unwrap();
+    client().step().unwrap();
+    ::std::thread::sleep(Duration::new(1, 0));
+    assert!(client().step().unwrap().reviews.is_empty());
+    assert_eq!(added(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unreadable_reviews_are_kept_aside() {
+    let dir = env::temp_dir().join(format!("nextaction-bad-reviews-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let store = StateStore::new(&dir);
+    File::create(store.reviews_path()).unwrap().write_all(br#"{"version": 99}"#).unwrap();
+
+    assert!(store.load_reviews().is_none());
+    assert!(!store.reviews_path().exists());
+    assert!(dir.join("reviews.json.bad").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
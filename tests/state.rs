@@ -0,0 +1,101 @@
+extern crate nextaction;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use nextaction::{NextAction, Todoist, FakeTodoist, StateStore};
+
+fn state_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("nextaction-state-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn client(fake: &FakeTodoist, dir: &PathBuf) -> NextAction {
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.set_state_dir(dir);
+    na
+}
+
+fn sync_tokens(fake: &FakeTodoist) -> Vec<String> {
+    fake.requests().iter().filter_map(|r| r.param("sync_token").map(|t| t.to_string())).collect()
+}
+
+#[test]
+fn resumes_after_restart() {
+    let dir = state_dir("resume");
+    let fake = FakeTodoist::new();
+    let project = fake.add_project("Book:", 1);
+    let first = fake.add_item(project, "outline", 1);
+    let second = fake.add_item(project, "draft", 1);
+
+    client(&fake, &dir).step().unwrap();
+    assert!(StateStore::new(&dir).load().is_some());
+
+    fake.complete_item(first);
+    client(&fake, &dir).step().unwrap();
+
+    let tokens = sync_tokens(&fake);
+    assert_eq!(tokens.len(), 2);
+    assert!(tokens[1] != "*");
+    // the labels were resolved in the first run and not created again
+    assert_eq!(fake.commands().iter().filter(|c| c["type"] == "label_add").count(), 2);
+    assert_eq!(fake.label_names(second), vec!["NextAction".to_string()]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn renamed_labels_are_looked_up_again() {
+    let dir = state_dir("renamed");
+    let fake = FakeTodoist::new();
+    fake.add_label("Next");
+    let project = fake.add_project("Book:", 1);
+    let outline = fake.add_item(project, "outline", 1);
+
+    client(&fake, &dir).step().unwrap();
+    let mut na = NextAction::with_todoist(Todoist::with_transport("secret", fake.clone()));
+    na.nextaction_name = "Next".into();
+    na.set_state_dir(&dir);
+    na.step().unwrap();
+
+    assert_eq!(sync_tokens(&fake), vec!["*".to_string(), "*".to_string()]);
+    // the label was there all along
+    assert!(fake.commands().iter().all(|c| c["args"]["name"] != "Next"));
+    assert!(fake.label_names(outline).contains(&"Next".to_string()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn discards_corrupt_state() {
+    let dir = state_dir("corrupt");
+    fs::create_dir_all(&dir).unwrap();
+    let store = StateStore::new(&dir);
+    File::create(store.path()).unwrap().write_all(b"{ not json").unwrap();
+
+    let fake = FakeTodoist::new();
+    fake.add_project("Book:", 1);
+    let mut na = client(&fake, &dir);
+    assert!(!store.path().exists());
+
+    na.step().unwrap();
+    assert_eq!(sync_tokens(&fake), vec!["*".to_string()]);
+    assert!(store.load().is_some());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn discards_outdated_state() {
+    let dir = state_dir("outdated");
+    fs::create_dir_all(&dir).unwrap();
+    let store = StateStore::new(&dir);
+    File::create(store.path()).unwrap().write_all(br#"{"version": 0, "sync_token": "42"}"#).unwrap();
+
+    assert!(store.load().is_none());
+    assert!(!store.path().exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,126 @@
+extern crate nextaction;
+use std::time::Duration;
+use nextaction::{NextAction, Todoist, MemoryTransport, ErrorKind};
+use nextaction::transport::HttpResponse;
+
+const SYNC: &'static str = r#"{
+    "full_sync": true,
+    "sync_token": "token-1",
+    "labels": [{"id": 1, "name": "NextAction"}, {"id": 2, "name": "Someday"}],
+    "projects": [{"id": 10, "name": "Work:", "item_order": 1, "indent": 1, "is_archived": 0}],
+    "items": [{
+        "id": 100, "project_id": 10, "content": "first", "indent": 1, "item_order": 1,
+        "date_added": "", "priority": 1, "all_day": false, "labels": [],
+        "is_deleted": 0, "is_archived": 0, "checked": 0, "in_history": 0
+    }, {
+        "id": 101, "project_id": 10, "content": "second", "indent": 1, "item_order": 2,
+        "date_added": "", "priority": 1, "all_day": false, "labels": [1],
+        "is_deleted": 0, "is_archived": 0, "checked": 0, "in_history": 0
+    }]
+}"#;
+
+#[test]
+fn sync_sends_form() {
+    let transport = MemoryTransport::new();
+    transport.push_json(SYNC);
+
+    let mut client = Todoist::with_transport("secret", transport.clone());
+    let result = client.sync_fields(&["projects", "items"]).unwrap();
+    assert_eq!(result.sync_token, "token-1");
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].param("token"), Some("secret"));
+    assert_eq!(requests[0].param("sync_token"), Some("*"));
+    assert_eq!(requests[0].param("resource_types"),
+               Some(r#"["projects","items"]"#));
+}
+
+#[test]
+fn add_label_reads_temp_id_mapping() {
+    let transport = MemoryTransport::new();
+    let mut client = Todoist::with_transport("secret", transport.clone());
+
+    // we don't know the uuids in advance, so answer after peeking at the request
+    let mut m = client.manager();
+    let (temp_id, uuid) = m.add_label("helloworld");
+    transport.push_json(format!(r#"{{"sync_status": {{"{}": "ok"}},
+                                    "temp_id_mapping": {{"{}": 42}}}}"#,
+                                uuid,
+                                temp_id));
+    m.flush().unwrap();
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].param("commands").unwrap().contains("label_add"));
+}
+
+#[test]
+fn step_offline() {
+    let transport = MemoryTransport::new();
+    transport.push_json(SYNC);
+    transport.push_json(r#"{"sync_status": {}, "temp_id_mapping": {}}"#);
+
+    // the canned answer doesn't know the uuids, so don't send them again
+    let mut client = Todoist::with_transport("secret", transport.clone());
+    client.set_retry(1, Duration::from_secs(0));
+    let mut na = NextAction::with_todoist(client);
+    na.step().unwrap();
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 2);
+    let commands = requests[1].param("commands").unwrap();
+    // "first" becomes the next action, "second" loses its label
+    assert!(commands.contains(r#""id":100"#));
+    assert!(commands.contains(r#""id":101"#));
+}
+
+fn status(code: u16, retry_after: Option<&str>) -> HttpResponse {
+    HttpResponse {
+        status: code,
+        headers: retry_after.iter().map(|v| ("Retry-After".to_string(), v.to_string())).collect(),
+        body: String::new(),
+    }
+}
+
+#[test]
+fn throttling_and_server_errors_are_typed() {
+    let transport = MemoryTransport::new();
+    transport.push_response(status(429, Some("7")));
+    transport.push_response(status(503, None));
+    transport.push_response(status(403, None));
+    let mut client = Todoist::with_transport("secret", transport.clone());
+
+    let err = client.sync().unwrap_err();
+    assert!(err.is_transient());
+    assert_eq!(err.retry_after(), Some(Duration::from_secs(7)));
+    match *err.kind() {
+        ErrorKind::RateLimited(Some(7)) => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+
+    let err = client.sync().unwrap_err();
+    assert!(err.is_transient());
+    match *err.kind() {
+        ErrorKind::ServerError(503, None) => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+
+    assert!(!client.sync().unwrap_err().is_transient());
+}
+
+#[test]
+fn flush_backs_off_from_server_errors() {
+    let transport = MemoryTransport::new();
+    let mut client = Todoist::with_transport("secret", transport.clone());
+    client.set_retry(3, Duration::from_secs(0));
+
+    let mut m = client.manager();
+    let uuid = m.complete_item(100);
+    transport.push_response(status(502, None));
+    transport.push_json(format!(r#"{{"sync_status": {{"{}": "ok"}}}}"#, uuid));
+    let result = m.flush().unwrap();
+
+    assert!(result.is_ok(&uuid));
+    assert_eq!(transport.requests().len(), 2);
+}
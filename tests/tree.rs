@@ -0,0 +1,94 @@
+extern crate nextaction;
+
+use nextaction::{TaskTree, Project, Item, ErrorKind};
+
+fn project(id: usize, indent: usize, parent_id: Option<usize>) -> Project {
+    Project {
+        id: id,
+        name: format!("project {}", id),
+        item_order: id,
+        indent: indent,
+        parent_id: parent_id,
+        ..Default::default()
+    }
+}
+
+fn item(id: usize, indent: usize, parent_id: Option<usize>) -> Item {
+    Item {
+        id: id,
+        project_id: 1,
+        content: format!("item {}", id),
+        item_order: id,
+        indent: indent,
+        parent_id: parent_id,
+        ..Default::default()
+    }
+}
+
+fn build(items: &[Item]) -> TaskTree {
+    TaskTree::build(&[project(1, 1, None)], items).unwrap()
+}
+
+#[test]
+fn nests_by_indent() {
+    let tree = build(&[item(10, 1, None), item(11, 2, None), item(12, 3, None), item(13, 2, None)]);
+    assert_eq!(tree.parent_of(10), Some(1));
+    assert_eq!(tree.parent_of(11), Some(10));
+    assert_eq!(tree.parent_of(12), Some(11));
+    assert_eq!(tree.parent_of(13), Some(10));
+}
+
+#[test]
+fn nests_by_parent_id_in_any_order() {
+    // the child sorts before its parent
+    let tree = build(&[item(10, 1, Some(12)), item(11, 1, None), item(12, 1, Some(11))]);
+    assert_eq!(tree.parent_of(12), Some(11));
+    assert_eq!(tree.parent_of(10), Some(12));
+}
+
+#[test]
+fn indent_without_parent_goes_to_the_top() {
+    // this used to panic in push_level
+    let tree = build(&[item(10, 3, None), item(11, 1, None)]);
+    assert_eq!(tree.parent_of(10), Some(1));
+    assert_eq!(tree.parent_of(11), Some(1));
+}
+
+#[test]
+fn indent_gap_attaches_to_nearest_ancestor() {
+    let tree = build(&[item(10, 1, None), item(11, 4, None)]);
+    assert_eq!(tree.parent_of(11), Some(10));
+}
+
+#[test]
+fn missing_and_looping_parents_go_to_the_top() {
+    let tree = build(&[item(10, 1, Some(99)), item(11, 1, Some(12)), item(12, 1, Some(11))]);
+    assert_eq!(tree.parent_of(10), Some(1));
+    // one of the two closes the loop and moves up, the other stays below it
+    let (a, b) = (tree.parent_of(11), tree.parent_of(12));
+    assert!((a, b) == (Some(1), Some(11)) || (a, b) == (Some(12), Some(1)));
+}
+
+#[test]
+fn sub_projects_by_parent_id() {
+    let projects = [project(1, 1, None), project(2, 1, Some(3)), project(3, 1, Some(1))];
+    let mut items = vec![item(10, 1, None)];
+    items[0].project_id = 2;
+    let tree = TaskTree::build(&projects, &items).unwrap();
+    assert_eq!(tree.parent_of(10), Some(2));
+}
+
+#[test]
+fn unknown_project_is_an_error() {
+    let mut orphan = item(10, 1, None);
+    orphan.project_id = 42;
+    match TaskTree::build(&[project(1, 1, None)], &[orphan]) {
+        Err(err) => {
+            match *err.kind() {
+                ErrorKind::MissingProject(10, 42) => {}
+                ref other => panic!("unexpected error {:?}", other),
+            }
+        }
+        Ok(_) => panic!("an item of an unknown project was placed"),
+    }
+}
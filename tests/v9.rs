@@ -0,0 +1,234 @@
+extern crate nextaction;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+use nextaction::{NextAction, Todoist, MemoryTransport, ApiVersion, TaskTree, ReviewRules, ReviewWatch};
+
+const SYNC: &'static str = r#"{
+    "full_sync": true,
+    "sync_token": "token-1",
+    "labels": [{"id": "2156154810", "name": "NextAction", "is_deleted": false},
+               {"id": "2156154811", "name": "Someday", "is_deleted": false}],
+    "projects": [{"id": "6Jf8VQXxpwv56VQ7", "name": "Work:", "parent_id": null, "child_order": 1,
+                  "is_archived": false, "is_deleted": false}],
+    "sections": [{"id": "7025", "name": "Later:", "project_id": "6Jf8VQXxpwv56VQ7",
+                  "section_order": 1, "is_archived": false, "is_deleted": false}],
+    "items": [{
+        "id": "2995104339", "project_id": "6Jf8VQXxpwv56VQ7", "content": "first",
+        "parent_id": null, "child_order": 1, "priority": 1, "labels": [],
+        "checked": false, "is_deleted": false, "added_at": "2026-10-01T08:00:00Z"
+    }, {
+        "id": "6X7rfFVPjhvv84XG", "project_id": "6Jf8VQXxpwv56VQ7", "content": "second",
+        "parent_id": null, "child_order": 2, "priority": 1, "labels": ["NextAction", "errand"],
+        "checked": false, "is_deleted": false,
+        "due": {"date": "2026-10-19", "string": "jeden Tag", "lang": "de", "is_recurring": true}
+    }, {
+        "id": "2995104340", "project_id": "6Jf8VQXxpwv56VQ7", "content": "first, part one",
+        "parent_id": "2995104339", "child_order": 1, "priority": 1, "labels": [],
+        "checked": true, "is_deleted": false
+    }]
+}"#;
+
+fn client(transport: &MemoryTransport) -> Todoist {
+    let mut client = Todoist::with_transport("secret", transport.clone());
+    client.set_api_version(ApiVersion::V9);
+    client.set_retry(1, Duration::from_secs(0));
+    client
+}
+
+fn json(s: &str) -> serde_json::Value {
+    serde_json::from_str(s).unwrap()
+}
+
+#[test]
+fn sync_translates_the_model() {
+    let transport = MemoryTransport::new();
+    transport.push_json(SYNC);
+
+    let result = client(&transport).sync().unwrap();
+    let items = result.items.unwrap();
+    assert_eq!(items[0].id, 2995104339);
+    assert_eq!(items[2].parent_id, Some(2995104339));
+    assert_eq!(items[2].checked, 1);
+    // Todoist says so, whatever the language of the date
+    assert!(items[1].is_recurring() && !items[0].is_recurring());
+    assert_eq!(items[1].date_string, Some("jeden Tag".into()));
+    assert_eq!(result.sections.unwrap()[0].id, 7025);
+    assert_eq!(items[1].project_id, result.projects.unwrap()[0].id);
+    let nextaction = result.labels.as_ref().unwrap()[0].id;
+    assert_eq!(items[1].labels[0], nextaction);
+
+    let requests = transport.requests();
+    assert_eq!(requests[0].url, "https://api.todoist.com/sync/v9/sync");
+    assert_eq!(requests[0].param("token"), None);
+    assert!(requests[0].headers.contains(&("Authorization".into(), "Bearer secret".into())));
+}
+
+#[test]
+fn step_sends_string_ids_and_label_names() {
+    let transport = MemoryTransport::new();
+    transport.push_json(SYNC);
+    transport.push_json(r#"{"sync_status": {}, "temp_id_mapping": {}}"#);
+
+    let mut na = NextAction::with_todoist(client(&transport));
+    na.step().unwrap();
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 2);
+    let mut commands: Vec<serde_json::Value> = json(requests[1].param("commands").unwrap())
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["args"].clone())
+        .collect();
+    commands.sort_by_key(|args| args["id"].as_str().unwrap().to_string());
+    // "first" becomes the next action, "second" keeps its other label
+    assert_eq!(commands,
+               vec![json(r#"{"id": "2995104339", "labels": ["NextAction"]}"#),
+                    json(r#"{"id": "6X7rfFVPjhvv84XG", "labels": ["errand"]}"#)]);
+}
+
+#[test]
+fn created_labels_are_known_by_name() {
+    let transport = MemoryTransport::new();
+    let mut client = client(&transport);
+
+    let mut m = client.manager();
+    let (temp_id, uuid) = m.add_label("Waiting");
+    transport.push_json(format!(r#"{{"sync_status": {{"{}": "ok"}},
+                                    "temp_id_mapping": {{"{}": "2156154812"}}}}"#,
+                                uuid,
+                                temp_id));
+    let label = m.flush().unwrap().id_of(&temp_id).unwrap();
+
+    assert_eq!(client.ids().string(label).unwrap(), "Waiting");
+}
+
+#[test]
+fn item_commands_take_single_ids() {
+    let transport = MemoryTransport::new();
+    transport.push_json(r#"{"sync_status": {}, "temp_id_mapping": {}}"#);
+    let mut client = client(&transport);
+
+    {
+        let mut m = client.manager();
+        let (project, _) = m.add_project("Garden");
+        m.add_item(project, "mow the lawn");
+        m.move_item(2995104339, 1, 2);
+        m.reorder_items(&[(2995104339, 3, 2)]);
+        m.flush().unwrap();
+    }
+
+    let commands = json(transport.requests()[0].param("commands").unwrap());
+    let types: Vec<&str> = commands.as_array().unwrap().iter().map(|c| c["type"].as_str().unwrap()).collect();
+    assert_eq!(types, vec!["project_add", "item_add", "item_move", "item_reorder"]);
+    assert_eq!(commands[1]["args"]["project_id"], commands[0]["temp_id"]);
+    assert_eq!(commands[2]["args"], json(r#"{"id": "2995104339", "project_id": "2"}"#));
+    assert_eq!(commands[3]["args"],
+               json(r#"{"items": [{"id": "2995104339", "child_order": 3}]}"#));
+
+    let mut m = client.manager();
+    m.push(nextaction::CommandKind::ItemDelete { ids: vec![1.into(), 2.into()] });
+    assert!(m.flush().is_err());
+}
+
+#[test]
+fn ids_of_deleted_objects_are_forgotten() {
+    let transport = MemoryTransport::new();
+    transport.push_json(SYNC);
+    transport.push_json(r#"{
+        "full_sync": false,
+        "sync_token": "token-2",
+        "items": [{
+            "id": "6X7rfFVPjhvv84XG", "project_id": "6Jf8VQXxpwv56VQ7", "content": "second",
+            "child_order": 2, "labels": [], "checked": false, "is_deleted": true
+        }]
+    }"#);
+    transport.push_json(r#"{
+        "full_sync": true,
+        "sync_token": "token-3",
+        "labels": [{"id": "2156154810", "name": "NextAction", "is_deleted": false}],
+        "projects": [],
+        "items": []
+    }"#);
+
+    let mut client = client(&transport);
+    let first = client.sync().unwrap();
+    let second = first.items.unwrap()[1].id;
+    // the project, the item, and the labels NextAction, Someday and errand
+    assert_eq!(client.ids().len(), 5);
+
+    client.sync().unwrap();
+    assert!(client.ids().string(second).is_err());
+    assert_eq!(client.ids().len(), 4);
+
+    // a full sync keeps what it mentions
+    client.sync().unwrap();
+    assert_eq!(client.ids().len(), 1);
+}
+
+#[test]
+fn reading_the_time_zone_keeps_the_ids() {
+    let dir = env::temp_dir().join(format!("nextaction-v9-tz-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let transport = MemoryTransport::new();
+    let client = || {
+        let mut na = NextAction::with_todoist(client(&transport));
+        na.set_state_dir(&dir);
+        na
+    };
+    // the user took the label off "second"
+    let unlabelled = r#"{"full_sync": false, "sync_token": "token-2", "projects": [], "labels": [], "notes": [],
+        "items": [{"id": "6X7rfFVPjhvv84XG", "project_id": "6Jf8VQXxpwv56VQ7", "content": "second",
+                   "child_order": 2, "labels": [], "checked": false, "is_deleted": false}]}"#;
+    let sent_labels = |request: usize| {
+        let commands = json(transport.requests()[request].param("commands").unwrap());
+        commands[0]["args"]["labels"].clone()
+    };
+    transport.push_json(SYNC.replace(r#""content": "first","#, r#""content": "first start:2000-01-01","#));
+    transport.push_json(r#"{"sync_status": {}, "temp_id_mapping": {}}"#);
+    client().step().unwrap();
+
+    // after a restart the first incremental round reads the user again
+    let mut na = client();
+    transport.push_json(unlabelled);
+    transport.push_json(r#"{"full_sync": true, "sync_token": "token-user", "user": {
+        "id": "1", "email": "ann@example.com", "full_name": "Ann", "inbox_project_id": "2203306141",
+        "tz_info": {"timezone": "Europe/Berlin", "gmt_string": "+01:00", "hours": 1, "minutes": 0}}}"#);
+    transport.push_json(r#"{"sync_status": {}, "temp_id_mapping": {}}"#);
+    na.step().unwrap();
+    assert_eq!(transport.requests()[3].param("resource_types"), Some(r#"["user"]"#));
+    assert_eq!(sent_labels(4), json(r#"["NextAction"]"#));
+
+    // the labels are still known by name the round after
+    transport.push_json(unlabelled);
+    transport.push_json(r#"{"sync_status": {}, "temp_id_mapping": {}}"#);
+    na.step().unwrap();
+    assert_eq!(transport.requests().len(), 7);
+    assert_eq!(sent_labels(6), json(r#"["NextAction"]"#));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_inbox_is_known() {
+    let transport = MemoryTransport::new();
+    transport.push_json(r#"{
+        "full_sync": true,
+        "sync_token": "token-1",
+        "projects": [{"id": "2203306141", "name": "Inbox", "child_order": 0, "inbox_project": true},
+                     {"id": "2203306142", "name": "Trip:", "child_order": 1}],
+        "items": []
+    }"#);
+
+    let projects = client(&transport).sync().unwrap().projects.unwrap();
+    assert_eq!(projects[0].extra["inbox_project"], json("true"));
+    assert!(projects[1].extra.get("inbox_project").is_none());
+
+    let tree = TaskTree::build(&projects, &[]).unwrap();
+    let mut watch = ReviewWatch::default();
+    watch.update(&tree, &ReviewRules::default(), 0);
+    assert_eq!(watch.update(&tree, &ReviewRules::default(), 365 * 24 * 60 * 60), vec![2203306142]);
+}
@@ -0,0 +1,69 @@
+extern crate nextaction;
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use nextaction::{WebhookHandler, Delivery, Wakeup};
+use nextaction::webhook::{verify, wait};
+
+const SECRET: &'static str = "0123456789abcdef";
+const BODY: &'static str = r#"{"event_name": "item:completed", "user_id": 2671355, "event_data": {"id": 2995104339}}"#;
+const SIGNATURE: &'static str = "8S3eHuueE0wL3zhPOkXfYMSk739/gbI+FYkRhhIwxKc=";
+
+fn delivery(event_name: &str) -> Delivery {
+    Delivery {
+        event_name: event_name.into(),
+        user_id: 2671355.into(),
+        event_data: Default::default(),
+    }
+}
+
+#[test]
+fn signatures() {
+    assert!(verify(SECRET, BODY.as_bytes(), SIGNATURE));
+    assert!(!verify("another secret", BODY.as_bytes(), SIGNATURE));
+    assert!(!verify(SECRET, BODY.replace("2995104339", "2995104340").as_bytes(), SIGNATURE));
+    assert!(!verify(SECRET, BODY.as_bytes(), "not base64!"));
+    assert!(!verify(SECRET, BODY.as_bytes(), "c2hvcnQ="));
+}
+
+#[test]
+fn only_signed_item_events_are_passed_on() {
+    let (tx, rx) = channel();
+    let handler = WebhookHandler::new(SECRET, tx);
+
+    assert_eq!(handler.receive(None, BODY.as_bytes()), 401);
+    assert_eq!(handler.receive(Some("9S3eHuueE0wL3zhPOkXfYMSk739/gbI+FYkRhhIwxKc="), BODY.as_bytes()),
+               401);
+    assert!(rx.try_recv().is_err());
+
+    assert_eq!(handler.receive(Some(SIGNATURE), BODY.as_bytes()), 200);
+    assert_eq!(rx.try_recv().unwrap().event_name, "item:completed");
+
+    assert!(!delivery("note:added").is_relevant());
+    assert!(delivery("item:updated").is_relevant());
+}
+
+#[test]
+fn bursts_are_debounced() {
+    let (tx, rx) = channel();
+    let sender = thread::spawn(move || for _ in 0..5 {
+        tx.send(delivery("item:updated")).unwrap();
+        thread::sleep(Duration::from_millis(10));
+    });
+
+    let wakeup = wait(&rx, Duration::from_millis(200), Duration::from_secs(5));
+    sender.join().unwrap();
+    assert_eq!(wakeup, Wakeup::Events(5));
+    // the sender is gone and nothing is left
+    assert_eq!(wait(&rx, Duration::from_millis(200), Duration::from_secs(5)), Wakeup::Closed);
+}
+
+#[test]
+fn quiet_periods_fall_back_to_a_round() {
+    let (_tx, rx) = channel();
+    let start = Instant::now();
+    assert_eq!(wait(&rx, Duration::from_millis(10), Duration::from_millis(50)),
+               Wakeup::Fallback);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}